@@ -0,0 +1,57 @@
+#[macro_use]
+extern crate criterion;
+extern crate ecds;
+
+use criterion::{BatchSize, Criterion};
+use ecds::keyvalue::{self, ReadOnlyHashMap, WritableHashMap};
+
+const INSERTS: u64 = 1_000_000;
+
+/// Keys the overwrite-heavy backlog keeps updating
+const HOT_KEYS: u64 = 16;
+
+/// A reader with `INSERTS` inserts waiting for it, cycling through `keys` distinct keys.
+/// The writer comes along so the reader doesn't see it gone.
+fn backlog<V, F>(keys: u64,
+                 value: &F,
+                 bulk_reserve: bool)
+                 -> (WritableHashMap<u64, V>, ReadOnlyHashMap<u64, V>)
+    where V: Clone,
+          F: Fn(u64) -> V
+{
+    let (mut w, r) = keyvalue::new();
+    for i in 0..INSERTS {
+        w.insert(i % keys, value(i)).unwrap();
+    }
+    (w, r.with_bulk_reserve(bulk_reserve))
+}
+
+/// Times only the drain, with and without reserving for the backlog up front
+fn drain<V, F>(c: &mut Criterion, name: &str, keys: u64, value: F)
+    where V: Clone,
+          F: Fn(u64) -> V
+{
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for &(label, bulk_reserve) in &[("with bulk reserve", true), ("without bulk reserve", false)] {
+        group.bench_function(label, |b| {
+            b.iter_batched(|| backlog(keys, &value, bulk_reserve),
+                           |(w, r)| {
+                               r.refresh().unwrap();
+                               (w, r)
+                           },
+                           BatchSize::PerIteration)
+        });
+    }
+    group.finish();
+}
+
+fn bulk_apply(c: &mut Criterion) {
+    drain(c, "1M inserts of distinct keys, 16 byte entries", INSERTS, |i| i);
+    drain(c, "1M inserts of distinct keys, 40 byte entries", INSERTS, |i| [i; 4]);
+    drain(c, "1M inserts of distinct keys, 136 byte entries", INSERTS, |i| [i; 16]);
+    drain(c, "1M inserts of 16 keys", HOT_KEYS, |i| [i; 4]);
+}
+
+criterion_group!(benches, bulk_apply);
+criterion_main!(benches);
@@ -1,9 +1,213 @@
+// error_chain's foreign links still call `description` and `cause`
+#![allow(deprecated)]
+
+use std::fmt;
+use std::io;
+use std::mem;
+
 
 error_chain!{
+    foreign_links{
+        Io(::std::io::Error);
+    }
+
     errors{
         Disconnected{
             description("Other side of channel has disconnected")
         }
+        WriterGone{
+            description("Writer has been dropped, no further changes will arrive")
+        }
+        Full{
+            description("Channel buffer is full")
+        }
+        HistoryLost{
+            description("Changes asked for are older than the reader's history")
+        }
+        Reentrant{
+            description("Reader was called from a callback while applying changes")
+        }
+        Timeout{
+            description("Gave up waiting before the other side caught up")
+        }
+    }
+
+}
+
+impl Error {
+    /// Whether this is `ErrorKind::Disconnected`, meaning no reader is left
+    ///
+    /// ```
+    /// use ecds::errors::ErrorKind;
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new::<u8, u8>();
+    /// drop(r);
+    /// let e = w.clear().unwrap_err();
+    /// assert!(e.is_disconnected());
+    /// assert_eq!(&ErrorKind::Disconnected, e.kind());
+    /// assert_eq!(e.clone(), e);
+    /// assert!(w.insert(1, 1).unwrap_err().is_disconnected());
+    /// ```
+    pub fn is_disconnected(&self) -> bool {
+        matches!(*self.kind(), ErrorKind::Disconnected)
+    }
+
+    /// Whether this is `ErrorKind::Full`, meaning the write can be retried once the
+    /// readers catch up, see `keyvalue::bounded`
+    pub fn is_full(&self) -> bool {
+        matches!(*self.kind(), ErrorKind::Full)
+    }
+
+    /// Whether this is `ErrorKind::Reentrant`, meaning a reader was used from inside one of
+    /// its own callbacks, such as `set_on_change`
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change, ReadOnlyHashMap};
+    /// use std::cell::RefCell;
+    /// use std::sync::mpsc::channel;
+    /// thread_local!(static READER: RefCell<Option<ReadOnlyHashMap<u8, u8>>> = RefCell::new(None));
+    ///
+    /// let (mut w, mut r) = keyvalue::new();
+    /// let (tx, rx) = channel();
+    /// r.set_on_change(move |_: &Change<u8, u8>| {
+    ///     READER.with(|r| {
+    ///         let e = r.borrow().as_ref().unwrap().get(&1).unwrap_err();
+    ///         tx.send(e.is_reentrant()).unwrap();
+    ///     })
+    /// });
+    /// READER.with(|slot| *slot.borrow_mut() = Some(r));
+    /// w.insert(1, 1).unwrap();
+    /// READER.with(|r| r.borrow().as_ref().unwrap().refresh().unwrap());
+    /// assert!(rx.recv().unwrap());
+    /// ```
+    pub fn is_reentrant(&self) -> bool {
+        matches!(*self.kind(), ErrorKind::Reentrant)
     }
 
+    /// Whether this is `ErrorKind::Timeout`, which is worth retrying, unlike
+    /// `Disconnected` or `WriterGone`
+    pub fn is_timeout(&self) -> bool {
+        matches!(*self.kind(), ErrorKind::Timeout)
+    }
+}
+
+/// Clones keep the kind, but not the backtrace or what caused the error
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        Error::from_kind(self.kind().clone())
+    }
+}
+
+/// Errors compare by kind alone
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+impl Eq for Error {}
+
+/// `io::Error` can't be cloned, so the clone of an `Io` error only keeps its
+/// `io::ErrorKind` and message
+impl Clone for ErrorKind {
+    fn clone(&self) -> Self {
+        match *self {
+            ErrorKind::Msg(ref s) => ErrorKind::Msg(s.clone()),
+            ErrorKind::Io(ref e) => ErrorKind::Io(io::Error::new(e.kind(), e.to_string())),
+            ErrorKind::Disconnected => ErrorKind::Disconnected,
+            ErrorKind::WriterGone => ErrorKind::WriterGone,
+            ErrorKind::Full => ErrorKind::Full,
+            ErrorKind::HistoryLost => ErrorKind::HistoryLost,
+            ErrorKind::Reentrant => ErrorKind::Reentrant,
+            ErrorKind::Timeout => ErrorKind::Timeout,
+        }
+    }
+}
+
+/// `Io` errors compare by their `io::ErrorKind`, since `io::Error` can't be compared
+impl PartialEq for ErrorKind {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ErrorKind::Msg(a), ErrorKind::Msg(b)) => a == b,
+            (ErrorKind::Io(a), ErrorKind::Io(b)) => a.kind() == b.kind(),
+            _ => mem::discriminant(self) == mem::discriminant(other),
+        }
+    }
 }
+
+impl Eq for ErrorKind {}
+
+/// Returned when a write couldn't be delivered to the readers, handing back the key and
+/// value so the caller can retry or reroute them.  Converts into an `Error` so `?` still
+/// works in functions returning `Result`.
+pub struct InsertError<K, V> {
+    pub key: K,
+    pub value: V,
+    pub error: Error,
+}
+
+/// Result of a write that hands the key and value back on failure
+pub type InsertResult<T, K, V> = ::std::result::Result<T, InsertError<K, V>>;
+
+impl<K, V> InsertError<K, V> {
+    pub fn kind(&self) -> &ErrorKind {
+        self.error.kind()
+    }
+
+    /// See `Error::is_disconnected`
+    pub fn is_disconnected(&self) -> bool {
+        self.error.is_disconnected()
+    }
+
+    /// See `Error::is_full`
+    pub fn is_full(&self) -> bool {
+        self.error.is_full()
+    }
+
+    pub fn into_inner(self) -> (K, V) {
+        (self.key, self.value)
+    }
+}
+
+impl<K, V> fmt::Debug for InsertError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InsertError")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<K, V> fmt::Display for InsertError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl<K, V> ::std::error::Error for InsertError<K, V> {}
+
+impl<K, V> From<InsertError<K, V>> for Error {
+    fn from(e: InsertError<K, V>) -> Self {
+        e.error
+    }
+}
+
+/// Returned by `WritableHashMap::try_insert` when the key is already present, with the
+/// value it holds and the one that was turned away
+pub struct OccupiedError<'a, V: 'a> {
+    pub current: &'a V,
+    pub value: V,
+}
+
+impl<'a, V> fmt::Debug for OccupiedError<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OccupiedError").finish()
+    }
+}
+
+impl<'a, V> fmt::Display for OccupiedError<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("key is already present")
+    }
+}
+
+impl<'a, V> ::std::error::Error for OccupiedError<'a, V> {}
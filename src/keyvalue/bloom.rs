@@ -0,0 +1,65 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+/// Bits set per key
+const PROBES: u64 = 3;
+
+/// A bloom filter that can be read from any thread while another adds to it, see
+/// `ReadOnlyHashMapSync::with_bloom`
+pub(super) struct Bloom {
+    words: Vec<AtomicU64>,
+    hasher: RandomState,
+}
+
+impl Bloom {
+    pub(super) fn new(bits: usize) -> Self {
+        Bloom {
+            words: (0..bits.div_ceil(64).max(1)).map(|_| AtomicU64::new(0)).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The bits standing for `k`, as word index and mask
+    fn probes<Q: ?Sized + Hash>(&self, k: &Q) -> impl Iterator<Item = (usize, u64)> {
+        let hash = self.hasher.hash_one(k);
+        // Derive each probe from two halves of the one hash, as in Kirsch and Mitzenmacher
+        let (a, b) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let bits = self.words.len() as u64 * 64;
+        (0..PROBES).map(move |i| {
+                            let bit = a.wrapping_add(i.wrapping_mul(b)) % bits;
+                            ((bit / 64) as usize, 1 << (bit % 64))
+                        })
+    }
+
+    pub(super) fn insert<Q: ?Sized + Hash>(&self, k: &Q) {
+        for (word, mask) in self.probes(k) {
+            self.words[word].fetch_or(mask, Ordering::Release);
+        }
+    }
+
+    /// `false` only if `k` was never inserted since the filter was last reset
+    pub(super) fn may_contain<Q: ?Sized + Hash>(&self, k: &Q) -> bool {
+        self.probes(k).all(|(word, mask)| self.words[word].load(Ordering::Acquire) & mask != 0)
+    }
+
+    /// An empty set of words, for building up a replacement with `mark` and `reset`
+    pub(super) fn blank(&self) -> Vec<u64> {
+        vec![0; self.words.len()]
+    }
+
+    pub(super) fn mark<Q: ?Sized + Hash>(&self, words: &mut [u64], k: &Q) {
+        for (word, mask) in self.probes(k) {
+            words[word] |= mask;
+        }
+    }
+
+    /// Swaps in `words` one word at a time.  A key marked in both the old and new words
+    /// stays visible throughout, so readers never miss one that was present all along.
+    pub(super) fn reset(&self, words: &[u64]) {
+        for (word, &bits) in self.words.iter().zip(words) {
+            word.store(bits, Ordering::Release);
+        }
+    }
+}
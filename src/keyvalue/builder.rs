@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use super::{debug_assert_seeded, pair, ReadOnlyHashMap, WritableHashMap};
+
+
+/// Sets up a pair with any combination of the options the free constructors offer one
+/// or two at a time.  `keyvalue::new` is the same as building with no options.
+///
+/// ```
+/// use ecds::keyvalue::Builder;
+/// let (mut w, r) = Builder::new()
+///     .capacity(100)
+///     .bounded(1)
+///     .preload(vec![(1, 1), (2, 2)])
+///     .build();
+/// w.insert_nonblocking(3, 3).unwrap();
+/// assert!(w.insert_nonblocking(4, 4).is_err());
+/// assert_eq!(vec![Some(1), Some(2), Some(3)], r.get_many(&[&1, &2, &3]).unwrap());
+/// assert!(r.capacity().unwrap() >= 100);
+/// ```
+pub struct Builder<K, V, S = RandomState> {
+    hasher: S,
+    capacity: usize,
+    bound: Option<usize>,
+    coalescing: Option<usize>,
+    preload: Vec<(K, V)>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Builder<K, V> {
+    pub fn new() -> Self {
+        Builder {
+            hasher: RandomState::new(),
+            capacity: 0,
+            bound: None,
+            coalescing: None,
+            preload: Vec::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for Builder<K, V> {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl<K, V, S> Builder<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher + Clone
+{
+    /// Pre-sizes both maps to hold at least `capacity` entries, see `keyvalue::with_capacity`
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Hashes both maps with clones of `hasher`, see `keyvalue::with_hasher`
+    pub fn hasher<T: BuildHasher + Clone>(self, hasher: T) -> Builder<K, V, T> {
+        Builder {
+            hasher,
+            capacity: self.capacity,
+            bound: self.bound,
+            coalescing: self.coalescing,
+            preload: self.preload,
+        }
+    }
+
+    /// Buffers at most `capacity` changes between the two, see `keyvalue::bounded`
+    pub fn bounded(mut self, capacity: usize) -> Self {
+        self.bound = Some(capacity);
+        self
+    }
+
+    /// Has the writer hold changes back, see `WritableHashMap::with_coalescing`
+    pub fn coalesce(mut self, limit: usize) -> Self {
+        self.coalescing = Some(limit);
+        self
+    }
+
+    /// Starts both maps out holding the pairs from `iter`, see `keyvalue::from_iter`.
+    /// They are never sent over the channel, so they don't count towards `bounded`.
+    pub fn preload<I: IntoIterator<Item = (K, V)>>(mut self, iter: I) -> Self {
+        self.preload.extend(iter);
+        self
+    }
+
+    pub fn build(self) -> (WritableHashMap<K, V, S>, ReadOnlyHashMap<K, V, S>) {
+        let capacity = self.capacity.max(self.preload.len());
+        let clone = self.hasher.clone();
+        debug_assert_eq!(self.hasher.hash_one(0u64),
+                         clone.hash_one(0u64),
+                         "clones of the hasher disagree");
+        let mut writer = HashMap::with_capacity_and_hasher(capacity, clone);
+        let mut reader = HashMap::with_capacity_and_hasher(capacity, self.hasher);
+        for (k, v) in self.preload {
+            reader.insert(k.clone(), v.clone());
+            writer.insert(k, v);
+        }
+        let (writer, reader) = pair(writer, reader, self.bound);
+        debug_assert_seeded(&writer, &reader);
+        match self.coalescing {
+            Some(limit) => (writer.with_coalescing(limit), reader),
+            None => (writer, reader),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash};
+
+use super::{Change, ReadOnlyHashMap};
+
+
+/// Iterator over every change a reader applies until its writer is gone, see
+/// `ReadOnlyHashMap::into_change_iter`
+pub struct IntoChanges<K, V, S> {
+    reader: ReadOnlyHashMap<K, V, S>,
+    changes: VecDeque<Change<K, V>>,
+}
+
+impl<K, V, S> Iterator for IntoChanges<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    type Item = Change<K, V>;
+
+    fn next(&mut self) -> Option<Change<K, V>> {
+        loop {
+            if let Some(change) = self.changes.pop_front() {
+                return Some(change);
+            }
+            match self.reader.drain_changes() {
+                Ok(ref changes) if changes.is_empty() => self.reader.inbox.wait(),
+                Ok(changes) => self.changes.extend(changes),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Turns this reader into an iterator over each change it applies, as
+    /// `drain_changes` would return them, waiting for the writer whenever there is nothing
+    /// new.  It ends once the writer is gone and everything it sent has been applied.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// use std::thread;
+    /// let (mut w, r) = keyvalue::new();
+    /// thread::spawn(move || {
+    ///     w.insert(1, 1).unwrap();
+    ///     w.clear().unwrap();
+    /// });
+    /// assert_eq!(vec![Change::Added { key: 1, value: 1 }, Change::Cleared],
+    ///            r.into_change_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn into_change_iter(self) -> IntoChanges<K, V, S> {
+        IntoChanges {
+            reader: self,
+            changes: VecDeque::new(),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, Instant};
+
+use errors::{InsertError, InsertResult, Result};
+
+use super::{Action, ChangeRef, ReadOnlyHashMap, WritableHashMap};
+
+
+/// Deadlines of the entries inserted with `WritableHashMap::insert_with_ttl`
+#[derive(Clone)]
+pub(super) struct Expiries<K> {
+    deadlines: HashMap<K, Instant>,
+    /// Earliest deadline, so checking whether anything is due doesn't need a scan
+    next: Option<Instant>,
+}
+
+impl<K: Eq + Hash> Expiries<K> {
+    pub(super) fn new() -> Self {
+        Expiries {
+            deadlines: HashMap::new(),
+            next: None,
+        }
+    }
+
+    pub(super) fn set(&mut self, k: K, at: Instant) {
+        self.next = Some(self.next.map_or(at, |next| next.min(at)));
+        self.deadlines.insert(k, at);
+    }
+
+    pub(super) fn deadline<Q>(&self, k: &Q) -> Option<Instant>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.deadlines.get(k).cloned()
+    }
+
+    pub(super) fn forget<Q>(&mut self, k: &Q)
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if !self.deadlines.is_empty() {
+            self.deadlines.remove(k);
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.deadlines.clear();
+        self.next = None;
+    }
+
+    /// Keeps the deadlines in step with a change applied by a reader.  Updates leave the
+    /// deadline alone, while anything else that replaces or removes an entry drops it.
+    pub(super) fn observe<V>(&mut self, change: &ChangeRef<'_, K, V>) {
+        match *change {
+            ChangeRef::Added(k, _) |
+            ChangeRef::Removed(k) => self.forget(k),
+            ChangeRef::Cleared => self.clear(),
+            ChangeRef::Updated(..) |
+            ChangeRef::Expires(..) => {}
+        }
+    }
+
+    /// Keeps the deadlines in step with an action sent by a writer
+    pub(super) fn track<V>(&mut self, action: &Action<K, V>)
+        where K: Clone
+    {
+        match *action {
+            Action::Add(ref k, _) |
+            Action::Remove(ref k) => self.forget(k),
+            Action::AddMany(ref pairs) => {
+                for (k, _) in pairs {
+                    self.forget(k);
+                }
+            }
+            Action::RemoveMany(ref keys) => {
+                for k in keys {
+                    self.forget(k);
+                }
+            }
+            Action::Update(..) |
+            Action::Reserve(_) |
+            Action::Shrink => {}
+            Action::Expire(ref k, at) => self.set(k.clone(), at),
+            Action::Clear |
+            Action::ClearKeepCapacity(_) |
+            Action::Replace(_) => self.clear(),
+            Action::Batch(ref actions) => {
+                for action in actions {
+                    self.track(action);
+                }
+            }
+        }
+    }
+
+    pub(super) fn is_due(&self) -> bool {
+        self.next.is_some_and(|next| next <= Instant::now())
+    }
+
+    /// Keys whose deadline has passed
+    pub(super) fn due(&self) -> Vec<K>
+        where K: Clone
+    {
+        let now = Instant::now();
+        self.deadlines
+            .iter()
+            .filter(|&(_, at)| *at <= now)
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+
+    /// Drops every entry that is past its deadline from `hashmap`, calling `notify` for
+    /// each, and returns how many there were
+    pub(super) fn expire<V, S, F>(&mut self,
+                                  hashmap: &mut HashMap<K, V, S>,
+                                  notify: &mut F)
+                                  -> usize
+        where S: BuildHasher,
+              F: FnMut(ChangeRef<'_, K, V>)
+    {
+        let now = Instant::now();
+        let mut expired = 0;
+        let mut next = None;
+        self.deadlines
+            .retain(|k, at| if *at <= now {
+                        if hashmap.remove(k).is_some() {
+                            expired += 1;
+                            notify(ChangeRef::Removed(k));
+                        }
+                        false
+                    } else {
+                        next = Some(next.map_or(*at, |next: Instant| next.min(*at)));
+                        true
+                    });
+        self.next = next;
+        expired
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Applies pending changes and drops every entry whose time to live has run out,
+    /// returning how many were dropped.  Lookups already do this, so calling it is only
+    /// needed to free the memory sooner.
+    pub fn purge_expired(&self) -> Result<usize> {
+        self.process_changes()?;
+        let mut hashmap = self.hashmap.borrow_mut();
+        let len = hashmap.len();
+        self.hooks
+            .borrow_mut()
+            .apply(&mut hashmap, Vec::new(), &mut |_: &ChangeRef<'_, K, V>| ());
+        Ok(len - hashmap.len())
+    }
+}
+
+impl<K, V, S> WritableHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Inserts an entry that readers treat as gone once `ttl` has passed.  Inserting the
+    /// same key again without a time to live, including through `entry` or `get_mut`,
+    /// makes it permanent.  Only
+    /// `ReadOnlyHashMap` honours the deadline; the writer keeps the entry until
+    /// `purge_expired` is called.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert_with_ttl(1, 1, Duration::from_millis(50)).unwrap();
+    /// w.insert_with_ttl(2, 2, Duration::from_millis(50)).unwrap();
+    /// w.insert(2, 3).unwrap();
+    /// w.insert_with_ttl(3, 3, Duration::from_millis(50)).unwrap();
+    /// w.entry(3).and_modify(|v| *v += 1).unwrap();
+    /// assert_eq!(Some(1), r.get(&1).unwrap());
+    /// thread::sleep(Duration::from_millis(100));
+    /// assert_eq!(None, r.get(&1).unwrap());
+    /// assert_eq!(Some(3), r.get(&2).unwrap());
+    /// assert_eq!(Some(4), r.get(&3).unwrap());
+    /// assert_eq!(1, w.purge_expired().unwrap());
+    /// assert_eq!(2, w.len());
+    /// ```
+    pub fn insert_with_ttl(&mut self,
+                           k: K,
+                           v: V,
+                           ttl: Duration)
+                           -> InsertResult<Option<V>, K, V> {
+        let expire = Action::Expire(k.clone(), Instant::now() + ttl);
+        match self.send(Action::Batch(vec![Action::Add(k.clone(), v.clone()), expire]),
+                        true) {
+            Ok(()) => Ok(self.hashmap.insert(k, v)),
+            Err(error) => {
+                Err(InsertError {
+                        key: k,
+                        value: v,
+                        error,
+                    })
+            }
+        }
+    }
+
+    /// Removes every entry whose time to live has run out, on this writer and its readers,
+    /// returning how many there were
+    pub fn purge_expired(&mut self) -> Result<usize> {
+        let due = self.expiries.due();
+        if due.is_empty() {
+            return Ok(0);
+        }
+        self.send(Action::RemoveMany(due.clone()), true)?;
+        for k in &due {
+            self.hashmap.remove(k);
+        }
+        Ok(due.len())
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+
+use super::{Action, ReadOnlyHashMap};
+
+
+/// Which writers' entries a reader holds, so it can tell when a clear from one wipes out
+/// another's, see `ReadOnlyHashMap::set_on_foreign_clear`
+pub(super) struct ForeignClears {
+    /// Writers that have added or updated entries since the last clear
+    contributors: HashSet<u64>,
+    callback: Box<dyn FnMut(u64, u64) + Send>,
+}
+
+impl ForeignClears {
+    /// Follows an action from `writer` as it is applied
+    pub(super) fn observe<K, V>(&mut self, writer: u64, action: &Action<K, V>) {
+        match *action {
+            Action::Add(..) |
+            Action::AddMany(_) |
+            Action::Update(..) => {
+                self.contributors.insert(writer);
+            }
+            Action::Clear |
+            Action::ClearKeepCapacity(_) => self.cleared(writer),
+            Action::Replace(ref pairs) => {
+                self.cleared(writer);
+                if !pairs.is_empty() {
+                    self.contributors.insert(writer);
+                }
+            }
+            Action::Batch(ref actions) => {
+                for action in actions {
+                    self.observe(writer, action);
+                }
+            }
+            Action::Remove(_) |
+            Action::RemoveMany(_) |
+            Action::Expire(..) |
+            Action::Reserve(_) |
+            Action::Shrink => {}
+        }
+    }
+
+    fn cleared(&mut self, by: u64) {
+        for wiped in self.contributors.drain() {
+            if wiped != by {
+                (self.callback)(by, wiped);
+            }
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Calls `f(by, wiped)` when a clear from the writer with id `by` removes entries the
+    /// writer with id `wiped` had added since the last clear, see
+    /// `WritableHashMap::writer_id`.  Only writers sharing a channel through `clone_writer`
+    /// can clear each other's entries.  Changes relayed by `net::connect` or
+    /// `from_transport` all come from writer 0.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::sync::mpsc::channel;
+    /// let (mut a, mut r) = keyvalue::new();
+    /// let mut b = a.clone_writer();
+    /// let (tx, rx) = channel();
+    /// r.set_on_foreign_clear(move |by, wiped| tx.send((by, wiped)).unwrap());
+    /// a.insert(1, 1).unwrap();
+    /// b.clear().unwrap();
+    /// b.insert(2, 2).unwrap();
+    /// b.clear().unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(vec![(b.writer_id(), a.writer_id())], rx.try_iter().collect::<Vec<_>>());
+    /// ```
+    pub fn set_on_foreign_clear<F>(&mut self, f: F)
+        where F: FnMut(u64, u64) + Send + 'static
+    {
+        self.hooks.get_mut().foreign_clears = Some(ForeignClears {
+                                                       contributors: HashSet::new(),
+                                                       callback: Box::new(f),
+                                                   });
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash};
+
+use errors::ErrorKind::HistoryLost;
+use errors::Result;
+
+use super::{Change, ChangeRef, ReadOnlyHashMap};
+
+
+/// The most recent changes a reader applied, with their sequence numbers, see
+/// `ReadOnlyHashMap::with_change_history`
+pub(super) struct History<K, V> {
+    capacity: usize,
+    changes: VecDeque<(u64, Change<K, V>)>,
+    /// Sequence number of the newest change pushed out of the buffer
+    lost: u64,
+    /// `ChangeRef::to_change`, kept here since readers apply changes without needing
+    /// `Clone`
+    to_change: ToChange<K, V>,
+}
+
+type ToChange<K, V> = fn(&ChangeRef<'_, K, V>) -> Option<Change<K, V>>;
+
+impl<K, V> History<K, V> {
+    pub(super) fn record(&mut self, seq: u64, change: &ChangeRef<'_, K, V>) {
+        if let Some(change) = (self.to_change)(change) {
+            self.changes.push_back((seq, change));
+            if self.changes.len() > self.capacity {
+                if let Some((seq, _)) = self.changes.pop_front() {
+                    self.lost = seq;
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Keeps the last `capacity` changes sent by the writer, so `changed_since` can
+    /// answer from them.  Entries dropped by this reader because of a time to live or
+    /// `with_capacity_limit` aren't recorded.
+    pub fn with_change_history(self, capacity: usize) -> Self {
+        self.hooks.borrow_mut().history = Some(History {
+                                                   capacity,
+                                                   changes: VecDeque::new(),
+                                                   lost: 0,
+                                                   to_change: |change| change.to_change(),
+                                               });
+        self
+    }
+
+    /// Applies pending changes and returns, in order, every one whose sequence number is
+    /// greater than `seq`.  Pass the reader's `applied_version` from the previous call to
+    /// see only what's new since then.  Fails with `HistoryLost` if some of those changes
+    /// have already been pushed out of the history, or if `with_change_history` wasn't
+    /// set, in which case the caller has to start over from a `snapshot`.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_change_history(2);
+    /// w.insert(1, 1).unwrap();
+    /// r.refresh().unwrap();
+    /// let seen = r.applied_version();
+    /// w.insert(2, 2).unwrap();
+    /// assert_eq!(vec![Change::Added { key: 2, value: 2 }], r.changed_since(seen).unwrap());
+    /// w.insert(3, 3).unwrap();
+    /// assert_eq!(2, r.changed_since(seen).unwrap().len());
+    /// assert!(r.changed_since(0).is_err());
+    /// ```
+    pub fn changed_since(&self, seq: u64) -> Result<Vec<Change<K, V>>> {
+        self.process_changes()?;
+        let hooks = self.hooks.borrow();
+        match hooks.history {
+            Some(ref history) if seq >= history.lost => {
+                Ok(history.changes
+                       .iter()
+                       .filter(|&&(s, _)| s > seq)
+                       .map(|(_, change)| change.clone())
+                       .collect())
+            }
+            _ => Err(HistoryLost.into()),
+        }
+    }
+}
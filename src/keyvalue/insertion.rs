@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+use errors::Result;
+
+use super::{ChangeRef, ReadOnlyHashMap};
+
+
+/// Order in which a reader's entries were inserted, see
+/// `ReadOnlyHashMap::with_insertion_order`
+pub(super) struct InsertionOrder<K> {
+    tick: u64,
+    ticks: HashMap<K, u64>,
+    order: BTreeMap<u64, K>,
+    /// `K::clone`, kept here since readers apply changes without needing `Clone`
+    clone: fn(&K) -> K,
+}
+
+impl<K: Eq + Hash> InsertionOrder<K> {
+    /// Moves `k` to the end, adding it if it isn't tracked yet
+    fn push(&mut self, k: &K) {
+        if let Some(tick) = self.ticks.remove(k) {
+            self.order.remove(&tick);
+        }
+        self.tick += 1;
+        self.ticks.insert((self.clone)(k), self.tick);
+        self.order.insert(self.tick, (self.clone)(k));
+    }
+
+    /// Keeps the order in step with a change applied by the reader.  Inserting a key that
+    /// is already present moves it to the end, while updating it in place doesn't.
+    pub(super) fn observe<V>(&mut self, change: &ChangeRef<'_, K, V>) {
+        match *change {
+            ChangeRef::Added(k, _) => self.push(k),
+            ChangeRef::Removed(k) => {
+                if let Some(tick) = self.ticks.remove(k) {
+                    self.order.remove(&tick);
+                }
+            }
+            ChangeRef::Cleared => {
+                self.ticks.clear();
+                self.order.clear();
+            }
+            ChangeRef::Updated(..) |
+            ChangeRef::Expires(..) => {}
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Tracks the order in which the writer inserted entries, for `iter_ordered`.  This
+    /// costs two extra copies of every key plus a counter per entry.  Entries already held
+    /// when this is called are ordered arbitrarily, ahead of anything inserted later.
+    pub fn with_insertion_order(self) -> Self {
+        {
+            let hashmap = self.hashmap.borrow();
+            let mut order = InsertionOrder {
+                tick: 0,
+                ticks: HashMap::new(),
+                order: BTreeMap::new(),
+                clone: K::clone,
+            };
+            for k in hashmap.keys() {
+                order.push(k);
+            }
+            self.hooks.borrow_mut().insertion = Some(order);
+        }
+        self
+    }
+
+    /// Point-in-time copy of every entry, oldest insertion first.  Without
+    /// `with_insertion_order` this is in no particular order, like `iter`.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_insertion_order();
+    /// for k in &["c", "a", "b"] {
+    ///     w.insert(*k, 0).unwrap();
+    /// }
+    /// w.insert("c", 1).unwrap();
+    /// w.remove("a").unwrap();
+    /// assert_eq!(vec![("b", 0), ("c", 1)], r.iter_ordered().unwrap());
+    /// ```
+    pub fn iter_ordered(&self) -> Result<Vec<(K, V)>> {
+        self.process_changes()?;
+        let hooks = self.hooks.borrow();
+        let order = match hooks.insertion {
+            Some(ref order) => order,
+            None => return self.iter(),
+        };
+        let hashmap = self.hashmap.borrow();
+        Ok(order.order
+               .values()
+               .filter_map(|k| hashmap.get_key_value(k))
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+}
@@ -0,0 +1,201 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+
+use super::{ChangeRef, Hooks, ReadOnlyHashMap, WritableHashMap};
+
+
+/// Order in which a size-limited reader's entries were last used, see
+/// `ReadOnlyHashMap::with_capacity_limit`
+pub(super) struct Lru<K> {
+    limit: usize,
+    tick: u64,
+    ticks: HashMap<K, u64>,
+    order: BTreeMap<u64, K>,
+    /// `K::clone`, kept here since readers apply changes without needing `Clone`
+    clone: fn(&K) -> K,
+}
+
+impl<K: Eq + Hash> Lru<K> {
+    /// Marks `k` as just used, if it is tracked
+    pub(super) fn touch<Q>(&mut self, k: &Q)
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if let Some(tick) = self.ticks.get_mut(k) {
+            let key = self.order.remove(tick).expect("every tracked key is ordered");
+            self.tick += 1;
+            *tick = self.tick;
+            self.order.insert(self.tick, key);
+        }
+    }
+
+    fn insert(&mut self, k: &K) {
+        self.tick += 1;
+        self.ticks.insert((self.clone)(k), self.tick);
+        self.order.insert(self.tick, (self.clone)(k));
+    }
+
+    /// Keeps the order in step with a change applied by the reader.  Writes count as a
+    /// use of the key.
+    pub(super) fn observe<V>(&mut self, change: &ChangeRef<'_, K, V>) {
+        match *change {
+            ChangeRef::Added(k, _) |
+            ChangeRef::Updated(k, _) => {
+                if self.ticks.contains_key(k) {
+                    self.touch(k);
+                } else {
+                    self.insert(k);
+                }
+            }
+            ChangeRef::Removed(k) => {
+                if let Some(tick) = self.ticks.remove(k) {
+                    self.order.remove(&tick);
+                }
+            }
+            ChangeRef::Cleared => {
+                self.ticks.clear();
+                self.order.clear();
+            }
+            ChangeRef::Expires(..) => {}
+        }
+    }
+
+    /// Drops the least recently used entries from `hashmap` until it fits, calling
+    /// `notify` for each and sending its key on `notices`
+    pub(super) fn evict<V, S, F>(&mut self,
+                                 hashmap: &mut HashMap<K, V, S>,
+                                 notices: Option<&Sender<K>>,
+                                 notify: &mut F)
+        where S: BuildHasher,
+              F: FnMut(ChangeRef<'_, K, V>)
+    {
+        while self.ticks.len() > self.limit {
+            let (_, k) = self.order.pop_first().expect("every tracked key is ordered");
+            self.ticks.remove(&k);
+            if hashmap.remove(&k).is_some() {
+                notify(ChangeRef::Removed(&k));
+                if let Some(notices) = notices {
+                    // The writer may be gone, in which case nobody is asking any more
+                    let _ = notices.send(k);
+                }
+            }
+        }
+    }
+}
+
+/// Keys evicted by readers, waiting for the writer to take them, see
+/// `ReadOnlyHashMap::report_evictions`
+pub(super) struct EvictionNotices<K> {
+    tx: Sender<K>,
+    /// Behind a lock only so the writer stays `Sync`
+    rx: Mutex<Receiver<K>>,
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Holds at most `limit` entries, dropping the least recently used ones once changes
+    /// from the writer take it over.  Lookups and writes both count as a use.
+    ///
+    /// This makes the reader a cache of the writer rather than a copy of it: an entry
+    /// dropped here is still present on the writer, but this reader won't see it again
+    /// until the writer next inserts it.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_capacity_limit(2);
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// assert_eq!(Some(1), r.get(&1).unwrap());
+    /// w.insert(3, 3).unwrap();
+    /// assert_eq!(2, r.len().unwrap());
+    /// assert_eq!(None, r.get(&2).unwrap());
+    /// assert_eq!(Some(&2), w.get(&2));
+    /// ```
+    pub fn with_capacity_limit(self, limit: usize) -> Self {
+        {
+            let mut hashmap = self.hashmap.borrow_mut();
+            let mut hooks = self.hooks.borrow_mut();
+            let mut lru = Lru {
+                limit,
+                tick: 0,
+                ticks: HashMap::new(),
+                order: BTreeMap::new(),
+                clone: K::clone,
+            };
+            for k in hashmap.keys() {
+                lru.insert(k);
+            }
+            let Hooks {
+                ref mut expiries,
+                ref mut insertion,
+                lru: ref mut slot,
+                ref evicted,
+                ..
+            } = *hooks;
+            lru.evict(&mut hashmap,
+                      evicted.as_ref(),
+                      &mut |change| {
+                               expiries.observe(&change);
+                               if let Some(ref mut insertion) = *insertion {
+                                   insertion.observe(&change);
+                               }
+                           });
+            *slot = Some(lru);
+        }
+        self
+    }
+
+    /// Tells `writer` about every entry this reader drops because of
+    /// `with_capacity_limit`, handing the keys out from its `take_eviction_notices`.  The
+    /// writer can then push the ones still in demand again, or stop tracking them.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, mut r) = keyvalue::new();
+    /// r.report_evictions(&mut w);
+    /// let r = r.with_capacity_limit(1);
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// assert!(w.take_eviction_notices().is_empty());
+    /// r.refresh().unwrap();
+    /// assert_eq!(vec![1], w.take_eviction_notices());
+    /// ```
+    pub fn report_evictions<W>(&mut self, writer: &mut WritableHashMap<K, V, W>)
+        where W: BuildHasher
+    {
+        let notices = writer.evictions.get_or_insert_with(|| {
+            let (tx, rx) = channel();
+            EvictionNotices { tx, rx: Mutex::new(rx) }
+        });
+        self.hooks.get_mut().evicted = Some(notices.tx.clone());
+    }
+}
+
+impl<K, V, S> WritableHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Takes the keys evicted by readers since the last call, oldest first, see
+    /// `ReadOnlyHashMap::report_evictions`.  Always empty unless a reader was set up to
+    /// report to this writer.
+    pub fn take_eviction_notices(&self) -> Vec<K> {
+        match self.evictions {
+            Some(ref notices) => {
+                notices.rx
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .try_iter()
+                    .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::ReadOnlyHashMap;
+
+
+/// Decides what a reader stores when the writer sends a value for a key, see
+/// `ReadOnlyHashMap::with_merge_policy`.  Any `Fn(Option<&V>, V) -> V` is a policy.
+pub trait MergePolicy<V> {
+    /// Combines the value the reader holds for the key, if any, with the one that arrived
+    fn merge(&self, existing: Option<&V>, incoming: V) -> V;
+}
+
+impl<V, F: Fn(Option<&V>, V) -> V> MergePolicy<V> for F {
+    fn merge(&self, existing: Option<&V>, incoming: V) -> V {
+        self(existing, incoming)
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Stores what `policy` makes of each value the writer sends, instead of overwriting.
+    /// Only this reader is affected, so it no longer mirrors the writer's local map.
+    /// Deltas sent by `update` apply as usual.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_merge_policy(|old: Option<&u32>, new| old.map_or(new, |old| old + new));
+    /// w.insert("hits", 2).unwrap();
+    /// w.insert("hits", 3).unwrap();
+    /// assert_eq!(Some(5), r.get("hits").unwrap());
+    /// assert_eq!(Some(&3), w.get("hits"));
+    /// ```
+    pub fn with_merge_policy<P: MergePolicy<V> + Send + 'static>(self, policy: P) -> Self {
+        self.hooks.borrow_mut().merge = Some(Box::new(policy));
+        self
+    }
+
+    /// Like `with_merge_policy`, for a function that only needs to look at the value that
+    /// arrived, such as one keeping a running maximum
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_apply_fn(|old: Option<&u32>, new: &u32| *old.unwrap_or(new).max(new));
+    /// w.insert("peak", 7).unwrap();
+    /// w.insert("peak", 3).unwrap();
+    /// assert_eq!(Some(7), r.get("peak").unwrap());
+    /// ```
+    pub fn with_apply_fn<F>(self, f: F) -> Self
+        where F: Fn(Option<&V>, &V) -> V + Send + 'static
+    {
+        self.with_merge_policy(move |existing: Option<&V>, incoming: V| f(existing, &incoming))
+    }
+}
@@ -0,0 +1,98 @@
+use std::hash::{BuildHasher, Hash};
+
+use errors::{Error, Result};
+use errors::ErrorKind::Reentrant;
+
+use super::ReadOnlyHashMap;
+
+
+/// Counters a reader keeps of its own activity, see `ReadOnlyHashMap::stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReaderStats {
+    /// Messages taken from the channel and applied
+    pub actions_applied: u64,
+    /// Calls to `get` and the other lookups that hand back a value
+    pub gets: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Times the reader checked the channel for changes
+    pub drains: u64,
+    /// Values turned away by `ReadOnlyHashMap::with_validator`
+    pub rejected: u64,
+}
+
+/// Callbacks for feeding a reader's activity into a metrics system, see
+/// `ReadOnlyHashMap::with_metrics`.  Every method does nothing by default.
+pub trait ReaderMetrics {
+    /// Called each time the reader applies changes, with how many messages it took from
+    /// the channel
+    fn on_actions_applied(&mut self, _count: usize) {}
+
+    /// Called on every `get`, with whether the key was found
+    fn on_get(&mut self, _hit: bool) {}
+
+    /// Called once, when the reader first finds the writer has gone away
+    fn on_disconnect(&mut self) {}
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Reports this reader's activity to `metrics`
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, ReaderMetrics};
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// struct Misses(Arc<AtomicUsize>);
+    /// impl ReaderMetrics for Misses {
+    ///     fn on_get(&mut self, hit: bool) {
+    ///         if !hit {
+    ///             self.0.fetch_add(1, Ordering::SeqCst);
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let misses = Arc::new(AtomicUsize::new(0));
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_metrics(Misses(misses.clone()));
+    /// w.insert(1, 1).unwrap();
+    /// r.get(&1).unwrap();
+    /// r.get(&2).unwrap();
+    /// assert_eq!(1, misses.load(Ordering::SeqCst));
+    /// ```
+    pub fn with_metrics<M: ReaderMetrics + Send + 'static>(self, metrics: M) -> Self {
+        self.hooks.borrow_mut().metrics = Some(Box::new(metrics));
+        self
+    }
+
+    /// Counts of what this reader has done since it was created or `reset_stats` was last
+    /// called.  Fails with `Reentrant` when called from one of this reader's callbacks.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// r.get(&1).unwrap();
+    /// r.get(&3).unwrap();
+    /// let stats = r.stats().unwrap();
+    /// assert_eq!(2, stats.actions_applied);
+    /// assert_eq!((2, 1, 1), (stats.gets, stats.hits, stats.misses));
+    /// assert_eq!(2, stats.drains);
+    /// r.reset_stats().unwrap();
+    /// assert_eq!(0, r.stats().unwrap().gets);
+    /// ```
+    pub fn stats(&self) -> Result<ReaderStats> {
+        let hooks = self.hooks.try_borrow().map_err(|_| Error::from(Reentrant))?;
+        Ok(hooks.stats)
+    }
+
+    pub fn reset_stats(&self) -> Result<()> {
+        let mut hooks = self.hooks.try_borrow_mut().map_err(|_| Error::from(Reentrant))?;
+        hooks.stats = ReaderStats::default();
+        Ok(())
+    }
+}
@@ -0,0 +1,3901 @@
+use std::borrow::Borrow;
+use std::cell::{Cell,Ref,RefCell};
+use std::clone::Clone;
+use std::mem;
+use std::collections::{HashMap,HashSet,VecDeque};
+use std::collections::hash_map::RandomState;
+use std::fmt::{self,Debug};
+use std::hash::{BuildHasher,Hash};
+use std::ops::{Deref,DerefMut,Index};
+use std::sync::{Arc,Mutex,PoisonError,Weak};
+use std::thread;
+use std::time::{Duration,Instant};
+use std::vec;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel,sync_channel,Receiver,RecvTimeoutError,Sender,SendError,SyncSender,
+                      TryRecvError,TrySendError};
+
+#[cfg(feature = "serde")]
+use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use errors::ErrorKind::{Disconnected,Full,Reentrant,Timeout,WriterGone};
+use errors::{Error,InsertError,InsertResult,OccupiedError,Result};
+
+mod bloom;
+mod builder;
+mod changes;
+mod expiry;
+mod foreign;
+mod history;
+mod insertion;
+mod lru;
+mod merge;
+mod metrics;
+#[cfg(feature = "net")]
+pub mod net;
+mod sync;
+mod transport;
+mod validate;
+mod wal;
+#[cfg(feature = "async")]
+mod wake;
+mod watch;
+
+pub use self::builder::Builder;
+pub use self::changes::IntoChanges;
+pub use self::sync::{ReadOnlyHashMapSync, SharedReadOnly, SharedReader, SnapshotReader};
+pub use self::merge::MergePolicy;
+pub use self::metrics::{ReaderMetrics, ReaderStats};
+pub use self::transport::{from_transport, ChangeSink, ChangeSource};
+pub use self::wal::{from_wal, Codec};
+#[cfg(feature = "async")]
+pub use self::wake::{ChangeStream, NextChanges};
+pub use self::watch::WatchHandle;
+use self::expiry::Expiries;
+use self::foreign::ForeignClears;
+use self::history::History;
+use self::insertion::InsertionOrder;
+use self::lru::{EvictionNotices, Lru};
+use self::validate::{screen, Validator};
+use self::wal::Wal;
+#[cfg(feature = "async")]
+use self::wake::{WakeOnDrop, WakeSlot};
+use self::watch::Watches;
+
+
+
+///
+/// ```
+/// use ecds::keyvalue;
+/// use std::thread;
+/// use std::time::Duration;
+/// let (mut w,r) = keyvalue::new();
+/// thread::spawn(move || {
+///     let _ = w.insert(1,2);
+///     thread::sleep(Duration::from_secs(60));
+/// });
+/// thread::sleep(Duration::from_secs(1));
+/// assert_eq!(2, r.get(&1).unwrap().unwrap());
+pub fn new<K: Eq + Hash + Clone, V: Clone>
+    ()
+    -> (WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)
+{
+    with_hasher(RandomState::new())
+}
+
+/// Creates a pair whose maps both hash with clones of `hasher`, so lookups behave
+/// identically on either side.  Debug builds check that a clone hashes the same way as
+/// the original, which a hasher that reseeds itself on `clone` would not.
+pub fn with_hasher<K: Eq + Hash + Clone, V: Clone, S: BuildHasher + Clone>
+    (hasher: S)
+    -> (WritableHashMap<K, V, S>, ReadOnlyHashMap<K, V, S>)
+{
+    with_capacity_and_hasher(0, hasher)
+}
+
+/// Creates a pair with both maps pre-sized to hold at least `capacity` entries
+pub fn with_capacity<K: Eq + Hash + Clone, V: Clone>
+    (capacity: usize)
+    -> (WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)
+{
+    with_capacity_and_hasher(capacity, RandomState::new())
+}
+
+/// Combination of `with_capacity` and `with_hasher`
+pub fn with_capacity_and_hasher<K: Eq + Hash + Clone, V: Clone, S: BuildHasher + Clone>
+    (capacity: usize,
+     hasher: S)
+     -> (WritableHashMap<K, V, S>, ReadOnlyHashMap<K, V, S>)
+{
+    let clone = hasher.clone();
+    debug_assert_eq!(hasher.hash_one(0u64),
+                     clone.hash_one(0u64),
+                     "clones of the hasher disagree");
+    pair(HashMap::with_capacity_and_hasher(capacity, clone),
+         HashMap::with_capacity_and_hasher(capacity, hasher),
+         None)
+}
+
+/// Creates a pair for using the map as a set of keys, keeping the map-shaped API.  Both
+/// sides hold what a `HashSet` does, since a `HashSet` is a `HashMap` with `()` values,
+/// and every change carries nothing but the key.  `set::new` wraps the same pair in a
+/// set-shaped API.
+///
+/// ```
+/// use ecds::keyvalue;
+/// let (mut w, r) = keyvalue::set_like();
+/// w.insert("a", ()).unwrap();
+/// assert!(r.contains_key("a").unwrap());
+/// assert_eq!(Some(()), r.get("a").unwrap());
+/// ```
+pub fn set_like<K: Eq + Hash + Clone>() -> (WritableHashMap<K, ()>, ReadOnlyHashMap<K, ()>) {
+    new()
+}
+
+/// Connects a writer and reader holding the given maps, using a channel bounded to
+/// `capacity` changes if one is given
+fn pair<K: Eq + Hash + Clone, V: Clone, S: BuildHasher>
+    (writer: HashMap<K, V, S>,
+     reader: HashMap<K, V, S>,
+     capacity: Option<usize>)
+     -> (WritableHashMap<K, V, S>, ReadOnlyHashMap<K, V, S>)
+{
+    let (tx, inbox) = open(capacity);
+    (WritableHashMap::from_map(writer, Outbox::new(vec![tx])),
+     ReadOnlyHashMap::from_map(reader, inbox))
+}
+
+/// Opens a new channel between a writer and one reader
+pub(crate) fn open<K, V>(capacity: Option<usize>) -> (Transmitter<K, V>, Inbox<K, V>) {
+    let pending = Arc::new(AtomicUsize::new(0));
+    let (reader, writers) = (Arc::new(()), Arc::new(()));
+    #[cfg(feature = "async")]
+    let wake = Arc::new(WakeSlot::new());
+    let (tx, rx) = match capacity {
+        Some(capacity) => {
+            let (tx, rx) = sync_channel(capacity);
+            (Tx::Bounded(tx, capacity), rx)
+        }
+        None => {
+            let (tx, rx) = channel();
+            (Tx::Unbounded(tx), rx)
+        }
+    };
+    (Transmitter {
+         tx,
+         pending: pending.clone(),
+         reader: Arc::downgrade(&reader),
+         writers: writers.clone(),
+         #[cfg(feature = "async")]
+         wake: WakeOnDrop(wake.clone()),
+     },
+     Inbox {
+         rx: Rx::Channel(rx),
+         pending,
+         _reader: reader,
+         writers: Arc::downgrade(&writers),
+         #[cfg(feature = "async")]
+         wake,
+         closed: Cell::new(false),
+         applied: Cell::new(0),
+         staged: RefCell::new(VecDeque::new()),
+     })
+}
+
+/// Builds a pair that both start out holding the contents of `hashmap`, without sending
+/// anything over the channel.
+fn seeded<K: Eq + Hash + Clone, V: Clone, S: BuildHasher + Clone>
+    (hashmap: HashMap<K, V, S>)
+    -> (WritableHashMap<K, V, S>, ReadOnlyHashMap<K, V, S>)
+{
+    let (writer, reader) = pair(hashmap.clone(), hashmap, None);
+    debug_assert_seeded(&writer, &reader);
+    (writer, reader)
+}
+
+/// Checks in debug builds that a pair seeded without going through the channel holds the
+/// same keys on both sides.  Values can't be compared without `V: PartialEq`, see
+/// `ReadOnlyHashMap::debug_assert_converged`.
+fn debug_assert_seeded<K: Eq + Hash, V, S: BuildHasher>(writer: &WritableHashMap<K, V, S>,
+                                                        reader: &ReadOnlyHashMap<K, V, S>) {
+    if cfg!(debug_assertions) {
+        let seeded = reader.hashmap.borrow();
+        assert!(writer.hashmap.len() == seeded.len() &&
+                writer.hashmap.keys().all(|k| seeded.contains_key(k)),
+                "reader and writer were seeded differently");
+    }
+}
+
+/// Creates a pair that both already hold the pairs from `iter`, so the reader has nothing
+/// to replay
+///
+/// ```
+/// use ecds::keyvalue;
+/// let (w, r) = keyvalue::from_iter(vec![(1, "a"), (2, "b")]);
+/// assert_eq!(0, r.pending_len());
+/// assert_eq!(Some("b"), r.get(&2).unwrap());
+/// assert_eq!(Some(&"a"), w.get(&1));
+/// ```
+pub fn from_iter<K: Eq + Hash + Clone, V: Clone, I: IntoIterator<Item = (K, V)>>
+    (iter: I)
+    -> (WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)
+{
+    seeded(iter.into_iter().collect())
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher + Clone
+{
+    /// Adds a reader to `writer` that starts out with a copy of its local map, then
+    /// follows the changes it makes from here on, so nothing has to be replayed to catch
+    /// it up.  The new reader has a channel of its own, never bounded, and like `fork`,
+    /// times to live aren't carried over.  A write that fails with `Full` because the
+    /// writer's bounded channel has no room doesn't reach the new reader either.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, ReadOnlyHashMap};
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// w.insert(2, 2).unwrap();
+    /// let replica = ReadOnlyHashMap::seeded_from(&mut w);
+    /// assert_eq!(0, replica.pending_len());
+    /// w.insert(3, 3).unwrap();
+    /// assert_eq!(3, replica.len().unwrap());
+    /// assert!(r.eq_writer(&w).unwrap());
+    ///
+    /// let (mut w, _r) = keyvalue::bounded(1);
+    /// let replica = ReadOnlyHashMap::seeded_from(&mut w);
+    /// w.insert_nonblocking(1, 1).unwrap();
+    /// assert!(w.insert_nonblocking(2, 2).is_err());
+    /// assert!(replica.eq_writer(&w).unwrap());
+    /// ```
+    pub fn seeded_from(writer: &mut WritableHashMap<K, V, S>) -> Self {
+        let (tx, inbox) = open(None);
+        writer.outbox.txs.push(tx);
+        ReadOnlyHashMap::from_map(writer.hashmap.clone(), inbox)
+    }
+}
+
+impl<K: Eq + Hash, V> ReadOnlyHashMap<K, V> {
+    /// Builds a reader with no writer, whose channel already holds `changes` in order, for
+    /// testing reader logic without a writer's timing getting in the way.  The reader
+    /// behaves as if its writer had made those changes and then been dropped, so `refresh`
+    /// applies them all and then fails with `WriterGone`.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{Change, ReadOnlyHashMap};
+    /// let r = ReadOnlyHashMap::from_changes(vec![Change::Added { key: 1, value: 1 },
+    ///                                            Change::Cleared,
+    ///                                            Change::Added { key: 2, value: 2 }]);
+    /// assert_eq!(3, r.pending_len());
+    /// assert!(r.refresh().is_err());
+    /// assert_eq!(3, r.applied_version());
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// ```
+    pub fn from_changes<I: IntoIterator<Item = Change<K, V>>>(changes: I) -> Self {
+        let (tx, inbox) = open(None);
+        for (seq, change) in (1..).zip(changes) {
+            // The inbox is still here to receive it, so this can't fail
+            let _ = tx.send((seq, 0, Action::from(change)), true);
+        }
+        ReadOnlyHashMap::from_map(HashMap::new(), inbox)
+    }
+}
+
+/// Restores a pair from a map previously serialized out of a `ReadOnlyHashMap`
+#[cfg(feature = "serde")]
+#[allow(clippy::type_complexity)]
+pub fn from_snapshot<'de, K, V, D>
+    (deserializer: D)
+    -> ::std::result::Result<(WritableHashMap<K, V>, ReadOnlyHashMap<K, V>), D::Error>
+    where K: Eq + Hash + Clone + Deserialize<'de>,
+          V: Clone + Deserialize<'de>,
+          D: Deserializer<'de>
+{
+    HashMap::deserialize(deserializer).map(seeded)
+}
+
+/// A writer whose values are reference counted, so they are shared with the readers
+/// rather than deep cloned
+pub type ArcWritableHashMap<K, V, S = RandomState> = WritableHashMap<K, Arc<V>, S>;
+
+/// The reader paired with an `ArcWritableHashMap`.  Lookups hand out another reference to
+/// the value the writer stored.
+pub type ArcReadOnlyHashMap<K, V, S = RandomState> = ReadOnlyHashMap<K, Arc<V>, S>;
+
+/// Like `new`, but values are stored behind an `Arc`.  Inserting ships a pointer instead
+/// of a copy of the value, which is cheaper for large values that don't change in place.
+///
+/// ```
+/// use ecds::keyvalue;
+/// use std::sync::Arc;
+/// let (mut w, r) = keyvalue::new_shared();
+/// let big = Arc::new(vec![0u8; 1 << 20]);
+/// w.insert("blob", big.clone()).unwrap();
+/// assert!(Arc::ptr_eq(&big, &r.get("blob").unwrap().unwrap()));
+/// ```
+pub fn new_shared<K: Eq + Hash + Clone, V>() -> (ArcWritableHashMap<K, V>, ArcReadOnlyHashMap<K, V>) {
+    new()
+}
+
+/// Creates a writer that fans out to `n` independent readers.  Each reader has its own
+/// channel, so a slow reader never holds up the writer or the other readers.
+///
+/// ```
+/// use ecds::keyvalue;
+/// let (mut w, readers) = keyvalue::new_multi(3);
+/// w.insert("a", 1).unwrap();
+/// for r in &readers {
+///     assert_eq!(Some(1), r.get("a").unwrap());
+/// }
+/// ```
+pub fn new_multi<K: Eq + Hash + Clone, V: Clone>
+    (n: usize)
+    -> (WritableHashMap<K, V>, Vec<ReadOnlyHashMap<K, V>>)
+{
+    let (txs, readers) = (0..n)
+        .map(|_| {
+                 let (tx, inbox) = open(None);
+                 (tx, ReadOnlyHashMap::from_map(HashMap::new(), inbox))
+             })
+        .unzip();
+    (WritableHashMap::from_map(HashMap::new(), Outbox::new(txs)), readers)
+}
+
+/// Creates a pair connected by a channel that buffers at most `capacity` changes.  Once
+/// the buffer fills, writes block until the reader catches up, or fail with `Full` when
+/// made through the `*_nonblocking` methods.
+///
+/// ```
+/// use ecds::errors::ErrorKind;
+/// use ecds::keyvalue;
+/// let (mut w, r) = keyvalue::bounded(1);
+/// w.insert_nonblocking(1, 1).unwrap();
+/// match w.insert_nonblocking(2, 2) {
+///     Err(e) => {
+///         assert!(e.is_full());
+///         assert_eq!(&ErrorKind::Full, e.kind());
+///         assert_eq!((2, 2), e.into_inner());
+///     }
+///     Ok(_) => panic!("channel should be full"),
+/// }
+/// assert!(!w.contains_key(&2));
+/// assert_eq!(Some(1), r.get(&1).unwrap());
+/// w.insert_nonblocking(2, 2).unwrap();
+/// ```
+pub fn bounded<K: Eq + Hash + Clone, V: Clone>
+    (capacity: usize)
+    -> (WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)
+{
+    pair(HashMap::new(), HashMap::new(), Some(capacity))
+}
+
+/// Makes every change `reader` has pending through `writer` as well, in the same order, so
+/// the readers of a second pair follow the first.  A clear is sent as a clear, so nothing
+/// from before it survives downstream.  Fails with `WriterGone` once the first pair's
+/// writer is gone and everything it sent has been passed on.
+///
+/// ```
+/// use ecds::keyvalue;
+/// let (mut a, from_a) = keyvalue::new();
+/// let (mut b, from_b) = keyvalue::from_iter(vec![(0, 0)]);
+/// a.insert(1, 1).unwrap();
+/// a.clear().unwrap();
+/// a.insert(2, 2).unwrap();
+/// keyvalue::pipe(&from_a, &mut b).unwrap();
+/// assert_eq!(vec![(2, 2)], from_b.iter().unwrap());
+/// drop(a);
+/// assert!(keyvalue::pipe(&from_a, &mut b).is_err());
+/// ```
+pub fn pipe<K, V, S, T>(reader: &ReadOnlyHashMap<K, V, S>,
+                        writer: &mut WritableHashMap<K, V, T>)
+                        -> Result<()>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher,
+          T: BuildHasher
+{
+    writer.apply_changes(reader.drain_changes()?)
+}
+
+/// A change to a value that is replayed identically on the writer and on every reader, so
+/// updates can ship the delta instead of the whole value.  Any `Fn(&mut V)` is a delta.
+pub trait ApplyDelta<V> {
+    fn apply(&self, value: &mut V);
+}
+
+impl<V, F: Fn(&mut V)> ApplyDelta<V> for F {
+    fn apply(&self, value: &mut V) {
+        self(value)
+    }
+}
+
+/// A change as a writer sends it to its readers, see `ChangeSink`
+#[derive(Clone)]
+pub enum Action<K, V> {
+    Add(K, V),
+    AddMany(Vec<(K, V)>),
+    Update(K, Arc<dyn ApplyDelta<V> + Send + Sync>),
+    Remove(K),
+    RemoveMany(Vec<K>),
+    Clear,
+    /// Clears the map, then makes room for at least this many entries, see
+    /// `WritableHashMap::clear_and_keep_capacity`
+    ClearKeepCapacity(usize),
+    /// Sets when an entry that is already present expires, see
+    /// `WritableHashMap::insert_with_ttl`
+    Expire(K, Instant),
+    /// Capacity hints, see `WritableHashMap::with_capacity_hints`.  They never change the
+    /// contents, so they are safe to drop.
+    Reserve(usize),
+    Shrink,
+    /// Clears the map, then fills it with the pairs, see `WritableHashMap::resync`
+    Replace(Vec<(K, V)>),
+    /// Applied as a unit, so readers never see part of it
+    Batch(Vec<Action<K, V>>),
+}
+
+impl<K: Eq + Hash, V> Action<K, V> {
+    fn sets_expiry(&self) -> bool {
+        match *self {
+            Action::Expire(..) => true,
+            Action::Batch(ref actions) => actions.iter().any(Action::sets_expiry),
+            _ => false,
+        }
+    }
+
+    /// Copy of the action without its values, enough to follow which entries it touches
+    fn keys_only(&self) -> Action<K, ()>
+        where K: Clone
+    {
+        match *self {
+            Action::Add(ref k, _) => Action::Add(k.clone(), ()),
+            Action::AddMany(ref pairs) => {
+                Action::AddMany(pairs.iter().map(|(k, _)| (k.clone(), ())).collect())
+            }
+            Action::Update(ref k, _) => Action::Update(k.clone(), Arc::new(|_: &mut ()| ())),
+            Action::Remove(ref k) => Action::Remove(k.clone()),
+            Action::RemoveMany(ref keys) => Action::RemoveMany(keys.clone()),
+            Action::Clear => Action::Clear,
+            Action::ClearKeepCapacity(additional) => Action::ClearKeepCapacity(additional),
+            Action::Expire(ref k, at) => Action::Expire(k.clone(), at),
+            Action::Reserve(additional) => Action::Reserve(additional),
+            Action::Shrink => Action::Shrink,
+            Action::Replace(ref pairs) => {
+                Action::Replace(pairs.iter().map(|(k, _)| (k.clone(), ())).collect())
+            }
+            Action::Batch(ref actions) => {
+                Action::Batch(actions.iter().map(Action::keys_only).collect())
+            }
+        }
+    }
+
+    /// Calls `f` with every key the action adds, in order, and with `None` at a clear
+    fn each_added<'a, F: FnMut(Option<&'a K>)>(&'a self, f: &mut F) {
+        match *self {
+            Action::Add(ref k, _) => f(Some(k)),
+            Action::AddMany(ref pairs) => pairs.iter().for_each(|(k, _)| f(Some(k))),
+            Action::Replace(ref pairs) => {
+                f(None);
+                pairs.iter().for_each(|(k, _)| f(Some(k)));
+            }
+            Action::Clear | Action::ClearKeepCapacity(_) => f(None),
+            Action::Batch(ref actions) => {
+                for action in actions {
+                    action.each_added(f);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Collects the keys this action touches since the last clear, in order, listing a key
+    /// again each time it is touched
+    fn touched_keys(self, cleared: &mut bool, touched: &mut Vec<K>) {
+        match self {
+            Action::Add(k, _) |
+            Action::Update(k, _) |
+            Action::Remove(k) |
+            Action::Expire(k, _) => {
+                touched.push(k);
+            }
+            Action::AddMany(pairs) => touched.extend(pairs.into_iter().map(|(k, _)| k)),
+            Action::RemoveMany(keys) => touched.extend(keys),
+            Action::Clear |
+            Action::ClearKeepCapacity(_) => {
+                *cleared = true;
+                touched.clear();
+            }
+            Action::Replace(pairs) => {
+                *cleared = true;
+                touched.clear();
+                touched.extend(pairs.into_iter().map(|(k, _)| k));
+            }
+            Action::Reserve(_) |
+            Action::Shrink => {}
+            Action::Batch(actions) => {
+                for action in actions {
+                    action.touched_keys(cleared, touched);
+                }
+            }
+        }
+    }
+
+    /// Drops each removal in a batch that a later add of the same key in that batch
+    /// overwrites anyway, see `WritableHashMap::with_readd_collapsing`.  Nothing is
+    /// dropped across a clear, or across anything else done to the key in between.
+    fn collapse_readds(self) -> Self
+        where K: Clone
+    {
+        let actions = match self {
+            Action::Batch(actions) => actions,
+            action => return action,
+        };
+        // Walking backwards, the keys added again later in the batch with nothing else
+        // done to them since
+        let mut readded = HashSet::new();
+        let mut kept = Vec::with_capacity(actions.len());
+        for action in actions.into_iter().rev() {
+            match action {
+                Action::Add(ref k, _) => {
+                    readded.insert(k.clone());
+                }
+                Action::AddMany(ref pairs) => readded.extend(pairs.iter().map(|(k, _)| k.clone())),
+                Action::Remove(ref k) if readded.contains(k) => continue,
+                Action::Remove(_) |
+                Action::Reserve(_) |
+                Action::Shrink => {}
+                Action::RemoveMany(mut keys) => {
+                    keys.retain(|k| !readded.contains(k));
+                    if !keys.is_empty() {
+                        kept.push(Action::RemoveMany(keys));
+                    }
+                    continue;
+                }
+                Action::Update(ref k, _) |
+                Action::Expire(ref k, _) => {
+                    readded.remove(k);
+                }
+                Action::Clear |
+                Action::ClearKeepCapacity(_) |
+                Action::Replace(_) |
+                Action::Batch(_) => readded.clear(),
+            }
+            kept.push(action);
+        }
+        kept.reverse();
+        Action::Batch(kept)
+    }
+
+    /// Applies the action, calling `notify` for each entry it touches
+    fn apply_to<S, F>(self, hashmap: &mut HashMap<K, V, S>, notify: &mut F)
+        where S: BuildHasher,
+              F: FnMut(ChangeRef<'_, K, V>)
+    {
+        self.apply_merging(hashmap, None, notify)
+    }
+
+    /// Like `apply_to`, but added values go through `merge` first if one is given
+    fn apply_merging<S, F>(self,
+                           hashmap: &mut HashMap<K, V, S>,
+                           merge: Option<&dyn MergePolicy<V>>,
+                           notify: &mut F)
+        where S: BuildHasher,
+              F: FnMut(ChangeRef<'_, K, V>)
+    {
+        match self {
+            Action::Add(k, v) => {
+                let v = match merge {
+                    Some(merge) => merge.merge(hashmap.get(&k), v),
+                    None => v,
+                };
+                notify(ChangeRef::Added(&k, &v));
+                hashmap.insert(k, v);
+            }
+            Action::AddMany(pairs) => {
+                for (k, v) in pairs {
+                    Action::Add(k, v).apply_merging(hashmap, merge, notify);
+                }
+            }
+            Action::Update(k, delta) => {
+                if let Some(v) = hashmap.get_mut(&k) {
+                    delta.apply(v);
+                    notify(ChangeRef::Updated(&k, v));
+                }
+            }
+            Action::Remove(k) => {
+                if let Some((k, _)) = hashmap.remove_entry(&k) {
+                    notify(ChangeRef::Removed(&k));
+                }
+            }
+            Action::RemoveMany(keys) => {
+                for k in keys {
+                    Action::Remove(k).apply_to(hashmap, notify);
+                }
+            }
+            Action::Clear => {
+                hashmap.clear();
+                notify(ChangeRef::Cleared);
+            }
+            Action::ClearKeepCapacity(additional) => {
+                Action::Clear.apply_merging(hashmap, None, notify);
+                hashmap.reserve(additional);
+            }
+            Action::Expire(k, at) => {
+                if hashmap.contains_key(&k) {
+                    notify(ChangeRef::Expires(k, at));
+                }
+            }
+            Action::Reserve(additional) => hashmap.reserve(additional),
+            Action::Shrink => hashmap.shrink_to_fit(),
+            // The pairs are the writer's whole state, so there is nothing to merge them with
+            Action::Replace(pairs) => {
+                Action::Clear.apply_merging(hashmap, None, notify);
+                for (k, v) in pairs {
+                    Action::Add(k, v).apply_merging(hashmap, None, notify);
+                }
+            }
+            Action::Batch(actions) => {
+                for action in actions {
+                    action.apply_merging(hashmap, merge, notify);
+                }
+            }
+        }
+    }
+}
+
+/// A change as seen by a reader once it has been applied.  Updates arrive as `Added`
+/// with the resulting value, and batches arrive as one `Change` per entry touched.
+/// `WritableHashMap::apply` takes these back, so a change stream can be replayed into
+/// another map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change<K, V> {
+    Added { key: K, value: V },
+    Removed { key: K },
+    Cleared,
+}
+
+impl<K, V> From<Change<K, V>> for Action<K, V> {
+    fn from(change: Change<K, V>) -> Self {
+        match change {
+            Change::Added { key, value } => Action::Add(key, value),
+            Change::Removed { key } => Action::Remove(key),
+            Change::Cleared => Action::Clear,
+        }
+    }
+}
+
+/// Borrowed form of `Change`, so maps that never hand changes out don't need to clone
+enum ChangeRef<'a, K: 'a, V: 'a> {
+    Added(&'a K, &'a V),
+    Updated(&'a K, &'a V),
+    Removed(&'a K),
+    Cleared,
+    /// Only of interest to readers that track expiry, so it isn't a public `Change`
+    Expires(K, Instant),
+}
+
+impl<'a, K: Clone, V: Clone> ChangeRef<'a, K, V> {
+    fn to_change(&self) -> Option<Change<K, V>> {
+        Some(match *self {
+                 ChangeRef::Added(k, v) |
+                 ChangeRef::Updated(k, v) => Change::Added { key: k.clone(), value: v.clone() },
+                 ChangeRef::Removed(k) => Change::Removed { key: k.clone() },
+                 ChangeRef::Cleared => Change::Cleared,
+                 ChangeRef::Expires(..) => return None,
+             })
+    }
+}
+
+/// Number of messages from which a reader reserves room for everything they add before
+/// applying them
+const BULK_APPLY: usize = 1024;
+
+/// Number of messages a reader looks at to estimate how much of a backlog is new keys,
+/// so estimating stays cheap next to applying it
+const BULK_SAMPLE: usize = 1024;
+
+/// Size of an entry from which reserving for a backlog beats growing the map as it
+/// goes.  Smaller entries are cheap to move when the map grows, and a big table from the
+/// start costs more in cache misses than that saves; see `benches/bulk_apply.rs`.
+const BULK_RESERVE_ENTRY: usize = 64;
+
+type Observer<K, V> = Box<dyn FnMut(ChangeRef<'_, K, V>) + Send>;
+
+/// Estimates how many keys `messages` add that `hashmap` won't already hold.  The first
+/// `BULK_SAMPLE` messages stand in for the rest: the distinct keys they add that aren't
+/// in the map, counting from the last clear among them, are taken to be as common in
+/// every message.  A backlog overwriting a few keys over and over hardly reserves
+/// anything that way, while a bulk load of fresh keys reserves for all of them.  A clear
+/// further on isn't looked for, since the map needs the room before it anyway.
+fn new_keys<K, V, S>(hashmap: &HashMap<K, V, S>, messages: &[Message<K, V>]) -> usize
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    let sample = &messages[..messages.len().min(BULK_SAMPLE)];
+    if sample.is_empty() {
+        return 0;
+    }
+    let mut cleared = false;
+    let mut new = HashSet::new();
+    for (_, _, action) in sample {
+        action.each_added(&mut |k| match k {
+                              Some(k) => {
+                                  if cleared || !hashmap.contains_key(k) {
+                                      new.insert(k);
+                                  }
+                              }
+                              None => {
+                                  new.clear();
+                                  cleared = true;
+                              }
+                          });
+    }
+    let estimate = messages.len() / sample.len() * new.len() +
+                   messages.len() % sample.len() * new.len() / sample.len();
+    if cleared {
+        // Capacity survives a clear, so the room the current entries take up is there too
+        estimate.saturating_sub(hashmap.len())
+    } else {
+        estimate
+    }
+}
+
+/// Per-reader extras that follow each change as it is applied
+struct Hooks<K, V> {
+    observer: Option<Observer<K, V>>,
+    expiries: Expiries<K>,
+    lru: Option<Lru<K>>,
+    metrics: Option<Box<dyn ReaderMetrics + Send>>,
+    history: Option<History<K, V>>,
+    merge: Option<Box<dyn MergePolicy<V> + Send>>,
+    insertion: Option<InsertionOrder<K>>,
+    watches: Option<Watches<K, V>>,
+    foreign_clears: Option<ForeignClears>,
+    /// Where evicted keys are reported, see `ReadOnlyHashMap::report_evictions`
+    evicted: Option<Sender<K>>,
+    validator: Option<Validator<K, V>>,
+    /// See `ReadOnlyHashMap::with_bulk_reserve`, unset to go by `BULK_RESERVE_ENTRY`
+    bulk_reserve: Option<bool>,
+    stats: ReaderStats,
+}
+
+impl<K: Eq + Hash, V> Hooks<K, V> {
+    fn new() -> Self {
+        Hooks {
+            observer: None,
+            expiries: Expiries::new(),
+            lru: None,
+            metrics: None,
+            history: None,
+            merge: None,
+            insertion: None,
+            watches: None,
+            foreign_clears: None,
+            evicted: None,
+            validator: None,
+            bulk_reserve: None,
+            stats: ReaderStats::default(),
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.expiries.is_due()
+    }
+
+    /// Applies `actions` to `hashmap`, then drops whatever has expired or no longer fits.
+    /// `report` sees the same changes as the observer.
+    fn apply<S, I, F>(&mut self, hashmap: &mut HashMap<K, V, S>, actions: I, report: &mut F)
+        where S: BuildHasher,
+              I: IntoIterator<Item = Message<K, V>>,
+              I::IntoIter: AsRef<[Message<K, V>]>,
+              F: FnMut(&ChangeRef<'_, K, V>)
+    {
+        let actions = actions.into_iter();
+        let len = actions.as_ref().len();
+        self.stats.actions_applied += len as u64;
+        if let Some(ref mut metrics) = self.metrics {
+            if len > 0 {
+                metrics.on_actions_applied(len);
+            }
+        }
+        // A backlog this size is usually a bulk load, so make room for it once instead of
+        // letting the map grow step by step
+        let bulk_reserve = self.bulk_reserve
+            .unwrap_or(mem::size_of::<(K, V)>() >= BULK_RESERVE_ENTRY);
+        if len >= BULK_APPLY && bulk_reserve {
+            hashmap.reserve(new_keys(hashmap, actions.as_ref()));
+        }
+        let Hooks {
+            ref mut observer,
+            ref mut expiries,
+            ref mut lru,
+            ref mut history,
+            ref merge,
+            ref mut insertion,
+            ref mut watches,
+            ref mut foreign_clears,
+            ref evicted,
+            ref validator,
+            ref mut stats,
+            ..
+        } = *self;
+        let merge = merge.as_ref().map(|merge| &**merge as &dyn MergePolicy<V>);
+        {
+            let mut notify = |seq: u64, change: ChangeRef<'_, K, V>| match change {
+                ChangeRef::Expires(k, at) => expiries.set(k, at),
+                change => {
+                    expiries.observe(&change);
+                    if let Some(ref mut lru) = *lru {
+                        lru.observe(&change);
+                    }
+                    if let Some(ref mut history) = *history {
+                        history.record(seq, &change);
+                    }
+                    if let Some(ref mut insertion) = *insertion {
+                        insertion.observe(&change);
+                    }
+                    if let Some(ref mut watches) = *watches {
+                        watches.observe(&change);
+                    }
+                    report(&change);
+                    if let Some(ref mut observer) = *observer {
+                        observer(change);
+                    }
+                }
+            };
+            for (seq, writer, action) in actions {
+                if let Some(ref mut foreign_clears) = *foreign_clears {
+                    foreign_clears.observe(writer, &action);
+                }
+                let action = match *validator {
+                    Some(ref valid) => {
+                        match screen(action, hashmap, &**valid, &mut stats.rejected) {
+                            Some(action) => action,
+                            None => continue,
+                        }
+                    }
+                    None => action,
+                };
+                action.apply_merging(hashmap, merge, &mut |change| notify(seq, change));
+            }
+        }
+        if expiries.is_due() {
+            expiries.expire(hashmap,
+                            &mut |change| {
+                                if let Some(ref mut lru) = *lru {
+                                    lru.observe(&change);
+                                }
+                                if let Some(ref mut insertion) = *insertion {
+                                    insertion.observe(&change);
+                                }
+                                if let Some(ref mut watches) = *watches {
+                                    watches.observe(&change);
+                                }
+                                report(&change);
+                                if let Some(ref mut observer) = *observer {
+                                    observer(change);
+                                }
+                            });
+        }
+        // Evicting only reflects this reader's limit, so it isn't reported as a change
+        if let Some(ref mut lru) = *lru {
+            lru.evict(hashmap,
+                      evicted.as_ref(),
+                      &mut |change| {
+                               expiries.observe(&change);
+                               if let Some(ref mut insertion) = *insertion {
+                                   insertion.observe(&change);
+                               }
+                               if let Some(ref mut watches) = *watches {
+                                   watches.observe(&change);
+                               }
+                           });
+        }
+    }
+
+    /// Keeps the extras in step with the reader dropping everything on its own, see
+    /// `ReadOnlyHashMap::clear_local`.  It isn't a change from the writer, so the observer
+    /// and history don't see it.
+    fn clear_local(&mut self) {
+        let change = ChangeRef::Cleared;
+        self.expiries.observe(&change);
+        if let Some(ref mut lru) = self.lru {
+            lru.observe(&change);
+        }
+        if let Some(ref mut insertion) = self.insertion {
+            insertion.observe(&change);
+        }
+        if let Some(ref mut watches) = self.watches {
+            watches.observe(&change);
+        }
+    }
+
+    /// Counts a lookup of `k` as a use
+    fn touch<Q>(&mut self, k: &Q)
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if let Some(ref mut lru) = self.lru {
+            lru.touch(k);
+        }
+    }
+
+    /// Records a `get` of `k`
+    fn looked_up<Q>(&mut self, k: &Q, hit: bool)
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.touch(k);
+        self.stats.gets += 1;
+        if hit {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        if let Some(ref mut metrics) = self.metrics {
+            metrics.on_get(hit);
+        }
+    }
+}
+
+pub struct ReadOnlyHashMap<K, V, S = RandomState> {
+    hashmap: RefCell<HashMap<K, V, S>>,
+    inbox: Inbox<K, V>,
+    hooks: RefCell<Hooks<K, V>>,
+    /// Lookups only apply changes if this long has passed since they were last applied
+    min_refresh: Option<Duration>,
+    last_refresh: Cell<Option<Instant>>,
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    fn from_map(hashmap: HashMap<K, V, S>, inbox: Inbox<K, V>) -> Self {
+        ReadOnlyHashMap {
+            hashmap: RefCell::new(hashmap),
+            inbox,
+            hooks: RefCell::new(Hooks::new()),
+            min_refresh: None,
+            last_refresh: Cell::new(None),
+        }
+    }
+
+    /// Lets lookups skip applying changes if they were last applied less than `interval`
+    /// ago, serving the map as it stands.  This bounds what draining costs under heavy
+    /// reads, at the price of results up to `interval` out of date.  `refresh` always
+    /// applies everything.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::time::Duration;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_min_refresh_interval(Duration::from_secs(60));
+    /// w.insert(1, 1).unwrap();
+    /// assert_eq!(Some(1), r.get(&1).unwrap());
+    /// w.insert(1, 2).unwrap();
+    /// assert_eq!(Some(1), r.get(&1).unwrap());
+    /// r.refresh().unwrap();
+    /// assert_eq!(Some(2), r.get(&1).unwrap());
+    /// ```
+    pub fn with_min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh = Some(interval);
+        self
+    }
+
+    /// Whether a large backlog of changes first estimates how many new keys it adds, so
+    /// the map makes room for them all at once instead of growing step by step.  Unless
+    /// set, this is only done for entries of at least 64 bytes: smaller ones measure
+    /// faster with the map growing as it goes.  A backlog that keeps overwriting the
+    /// same keys reserves next to nothing either way.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_bulk_reserve(true);
+    /// for i in 0..2000 {
+    ///     w.insert(i % 10, i).unwrap();
+    /// }
+    /// assert_eq!(10, r.len().unwrap());
+    /// assert!(r.capacity().unwrap() < 2000);
+    /// ```
+    pub fn with_bulk_reserve(self, enabled: bool) -> Self {
+        self.hooks.borrow_mut().bulk_reserve = Some(enabled);
+        self
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        self.hooks.borrow_mut().touch(k);
+        Ok(self.hashmap.borrow().contains_key(k))
+    }
+
+    /// `contains_key` for a key the caller already owns, see `get_owned`
+    pub fn contains_key_owned(&self, k: K) -> Result<bool> {
+        self.contains_key(&k)
+    }
+
+    /// Applies pending changes, then checks every value for one equal to `v`.  This is a
+    /// linear scan.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, "a").unwrap();
+    /// assert!(r.contains_value(&"a").unwrap());
+    /// assert!(!r.contains_value(&"b").unwrap());
+    /// ```
+    pub fn contains_value(&self, v: &V) -> Result<bool>
+        where V: PartialEq
+    {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().values().any(|value| value == v))
+    }
+
+    /// Like `contains_key`, but without applying pending changes, see `try_get`
+    pub fn try_contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let (mut hooks, hashmap) = match (self.hooks.try_borrow_mut(), self.hashmap.try_borrow()) {
+            (Ok(hooks), Ok(hashmap)) => (hooks, hashmap),
+            _ => return false,
+        };
+        hooks.touch(k);
+        hashmap.contains_key(k)
+    }
+
+    /// Like `get`, but borrows the value instead of cloning it.  While the returned `Ref`
+    /// is alive this reader stops applying changes, serving the map as it stood, so don't
+    /// hold on to it for long.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, vec![0; 1024]).unwrap();
+    /// {
+    ///     let v = r.get_ref(&1).unwrap().unwrap();
+    ///     w.remove(&1).unwrap();
+    ///     assert!(r.contains_key(&1).unwrap());
+    ///     assert_eq!(1024, v.len());
+    /// }
+    /// assert!(!r.contains_key(&1).unwrap());
+    /// ```
+    pub fn get_ref<Q>(&self, k: &Q) -> Result<Option<Ref<'_, V>>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        let value = Ref::filter_map(self.hashmap.borrow(), |hashmap| hashmap.get(k)).ok();
+        self.hooks.borrow_mut().looked_up(k, value.is_some());
+        Ok(value)
+    }
+
+    /// Like `get`, but hands back what `f` makes of the value instead of a clone of it,
+    /// for when only part of it is needed.  The map is only borrowed while `f` runs.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, vec![0; 1024]).unwrap();
+    /// assert_eq!(Some(1024), r.get_mapped(&1, |v| v.len()).unwrap());
+    /// assert_eq!(None, r.get_mapped(&2, |v| v.len()).unwrap());
+    /// ```
+    pub fn get_mapped<Q, R, F>(&self, k: &Q, f: F) -> Result<Option<R>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq,
+              F: FnOnce(&V) -> R
+    {
+        self.process_changes()?;
+        let value = self.hashmap.borrow().get(k).map(f);
+        self.hooks.borrow_mut().looked_up(k, value.is_some());
+        Ok(value)
+    }
+
+    /// Applies pending changes once, then runs `f` on the map as it stands, returning
+    /// what it computes.  Every lookup `f` makes sees the same state and nothing is
+    /// cloned.  As with `get_ref`, the map stays borrowed while `f` runs, so calls back
+    /// into this reader from inside `f` don't apply any changes.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("a", 1).unwrap();
+    /// w.insert("b", 2).unwrap();
+    /// let sum = r.with_settled(|map| map["a"] + map["b"]).unwrap();
+    /// assert_eq!(3, sum);
+    /// ```
+    pub fn with_settled<R, F: FnOnce(&HashMap<K, V, S>) -> R>(&self, f: F) -> Result<R> {
+        self.process_changes()?;
+        Ok(f(&self.hashmap.borrow()))
+    }
+
+    /// Applies pending changes, then folds `f` over every entry without cloning any of
+    /// them, in no particular order.  The map is only borrowed while folding.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.extend(vec![("a", 1), ("b", 2), ("c", 3)]).unwrap();
+    /// assert_eq!(6, r.fold(0, |sum, _, v| sum + v).unwrap());
+    /// assert_eq!(2, r.fold(0, |n, _, &v| if v > 1 { n + 1 } else { n }).unwrap());
+    /// ```
+    pub fn fold<B, F: FnMut(B, &K, &V) -> B>(&self, init: B, mut f: F) -> Result<B> {
+        self.with_settled(|hashmap| hashmap.iter().fold(init, |acc, (k, v)| f(acc, k, v)))
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().len())
+    }
+
+    /// Capacity of the reader's map
+    pub fn capacity(&self) -> Result<usize> {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().capacity())
+    }
+
+    /// Like `capacity`, but without applying pending changes, for keeping an eye on the
+    /// reader's memory use from outside the lookup path
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.extend((0..100).map(|i| (i, i))).unwrap();
+    /// assert_eq!(0, r.try_capacity());
+    /// r.refresh().unwrap();
+    /// assert!(r.try_capacity() >= 100);
+    /// ```
+    pub fn try_capacity(&self) -> usize {
+        self.hashmap.borrow().capacity()
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().is_empty())
+    }
+
+    /// Point-in-time copy of the keys, see `iter`
+    pub fn keys(&self) -> Result<Vec<K>>
+        where K: Clone
+    {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().keys().cloned().collect())
+    }
+
+    /// Applies pending changes, then checks whether this reader holds exactly what `writer`
+    /// does
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// w.remove(&1).unwrap();
+    /// assert!(r.eq_writer(&w).unwrap());
+    /// ```
+    pub fn eq_writer(&self, writer: &WritableHashMap<K, V, S>) -> Result<bool>
+        where V: PartialEq
+    {
+        self.process_changes()?;
+        Ok(*self.hashmap.borrow() == writer.hashmap)
+    }
+
+    /// In debug builds, panics unless this reader holds exactly what `writer` does, as it
+    /// stands and without applying pending changes.  Meant for pairs that should start out
+    /// alike, such as those from `from_iter`.  Release builds skip the check.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1), (2, 2)]);
+    /// r.debug_assert_converged(&w);
+    /// w.insert(3, 3).unwrap();
+    /// r.refresh().unwrap();
+    /// r.debug_assert_converged(&w);
+    /// ```
+    pub fn debug_assert_converged(&self, writer: &WritableHashMap<K, V, S>)
+        where V: PartialEq
+    {
+        debug_assert!(*self.hashmap.borrow() == writer.hashmap,
+                      "reader has diverged from its writer");
+    }
+
+    /// Empties this reader without applying or dropping any pending changes, and without
+    /// involving the writer, to throw away the cached view and build it up again.  From
+    /// then on it no longer matches the writer, only holding what changes after this
+    /// bring back, until the writer next does a `resync` or `clear`.  Fails with
+    /// `Reentrant` when called from one of this reader's callbacks, or while a value from
+    /// `get_ref` is still borrowed.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// w.insert(2, 2).unwrap();
+    /// r.clear_local().unwrap();
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// w.resync().unwrap();
+    /// assert_eq!(2, r.len().unwrap());
+    /// ```
+    pub fn clear_local(&self) -> Result<()> {
+        let mut hooks = self.hooks.try_borrow_mut().map_err(|_| Error::from(Reentrant))?;
+        let mut hashmap = self.hashmap.try_borrow_mut().map_err(|_| Error::from(Reentrant))?;
+        hashmap.clear();
+        hooks.clear_local();
+        Ok(())
+    }
+
+    /// Applies everything the writer has sent so far without doing a lookup.  Once the
+    /// writer is gone and every change it sent has been applied this returns `WriterGone`,
+    /// while lookups keep succeeding against the last known state.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 2).unwrap();
+    /// drop(w);
+    /// assert!(r.refresh().is_err());
+    /// assert_eq!(Some(2), r.get(&1).unwrap());
+    /// ```
+    pub fn refresh(&self) -> Result<()> {
+        self.apply_pending()?;
+        self.inbox.check_open()
+    }
+
+    /// Number of changes the writer has sent that this reader hasn't applied yet
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// assert_eq!(2, r.pending_len());
+    /// r.refresh().unwrap();
+    /// assert_eq!(0, r.pending_len());
+    /// ```
+    pub fn pending_len(&self) -> usize {
+        self.inbox.pending.load(Ordering::SeqCst)
+    }
+
+    /// Whether anything is waiting to be applied, found by looking at the channel directly
+    /// rather than at the counter behind `pending_len`.  That also works for readers made
+    /// with `from_transport`, whose `pending_len` is always zero.  A change taken to find
+    /// out is kept, and applied first next time.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::sync::mpsc::channel;
+    /// let (tx, rx) = channel();
+    /// let (mut w, r) = keyvalue::from_transport(tx, rx);
+    /// assert!(!r.has_pending());
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(1, 2).unwrap();
+    /// assert!(r.has_pending());
+    /// assert_eq!(0, r.pending_len());
+    /// assert_eq!(Some(2), r.get(&1).unwrap());
+    /// assert!(!r.has_pending());
+    /// ```
+    pub fn has_pending(&self) -> bool {
+        self.inbox.has_pending()
+    }
+
+    /// Whether every change sent so far has been applied, checked without applying
+    /// anything.  Fails with `WriterGone` once the writer has been dropped.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// assert!(r.is_current().unwrap());
+    /// w.insert(1, 1).unwrap();
+    /// assert!(!r.is_current().unwrap());
+    /// r.refresh().unwrap();
+    /// assert!(r.is_current().unwrap());
+    /// drop(w);
+    /// assert!(r.is_current().is_err());
+    /// ```
+    pub fn is_current(&self) -> Result<bool> {
+        if self.inbox.writer_gone() {
+            return Err(WriterGone.into());
+        }
+        Ok(self.pending_len() == 0)
+    }
+
+    /// Whether the writer is still around, checked without applying anything.  Changes it
+    /// sent before going away are still applied by the next lookup.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::sync::mpsc::channel;
+    /// let (tx, rx) = channel();
+    /// let (mut w, r) = keyvalue::from_transport(tx, rx);
+    /// w.insert(1, 1).unwrap();
+    /// assert!(r.is_writer_connected());
+    /// drop(w);
+    /// assert!(!r.is_writer_connected());
+    /// assert_eq!(Some(1), r.get(&1).unwrap());
+    /// ```
+    pub fn is_writer_connected(&self) -> bool {
+        // A custom source only tells whether it's closed when asked for changes, so take
+        // them and leave them staged
+        if let Rx::Custom(_) = self.inbox.rx {
+            self.inbox.stage();
+        }
+        !self.inbox.writer_gone()
+    }
+
+    /// Applies everything the writer has sent so far, regardless of
+    /// `with_min_refresh_interval`, then hands back the reader's map
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// drop(w);
+    /// assert_eq!(Some(&1), r.into_inner().unwrap().get(&1));
+    /// ```
+    pub fn into_inner(self) -> Result<HashMap<K, V, S>> {
+        self.apply_pending()?;
+        Ok(self.hashmap.into_inner())
+    }
+
+    /// Sequence number of the last change applied from the writer, see
+    /// `WritableHashMap::version`
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// assert_eq!(2, w.version() - r.applied_version());
+    /// r.refresh().unwrap();
+    /// assert_eq!(w.version(), r.applied_version());
+    /// ```
+    pub fn applied_version(&self) -> u64 {
+        self.inbox.applied.get()
+    }
+
+    fn process_changes(&self) -> Result<()> {
+        // Changes are applied with the hooks borrowed, so finding them borrowed means this
+        // was called from a callback while changes were being applied
+        if self.hooks.try_borrow_mut().is_err() {
+            return Err(Reentrant.into());
+        }
+        if let Some(interval) = self.min_refresh {
+            if self.last_refresh.get().is_some_and(|at| at.elapsed() < interval) {
+                return Ok(());
+            }
+        }
+        self.apply_pending()
+    }
+
+    fn apply_pending(&self) -> Result<()> {
+        self.apply_pending_with(&mut |_: &ChangeRef<'_, K, V>| ())
+    }
+
+    /// Applies everything that has arrived, passing each change made to `report`
+    fn apply_pending_with<F>(&self, report: &mut F) -> Result<()>
+        where F: FnMut(&ChangeRef<'_, K, V>)
+    {
+        let mut hooks = self.hooks.try_borrow_mut().map_err(|_| Error::from(Reentrant))?;
+        // Leave the changes queued while a value borrowed through `get_ref` is alive
+        if self.hashmap.try_borrow_mut().is_err() {
+            return Ok(());
+        }
+        if self.min_refresh.is_some() {
+            self.last_refresh.set(Some(Instant::now()));
+        }
+        let was_open = !self.inbox.closed.get();
+        let actions = self.inbox.take();
+        hooks.stats.drains += 1;
+        if was_open && self.inbox.closed.get() {
+            if let Some(ref mut metrics) = hooks.metrics {
+                metrics.on_disconnect();
+            }
+        }
+        if !actions.as_ref().is_empty() || hooks.is_due() {
+            hooks.apply(&mut self.hashmap.borrow_mut(), actions, report);
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Calls `f` for every change as it gets applied, in the order the writer sent them.
+    /// The callback runs while the map is being updated, so it must not call back into
+    /// this reader.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// use std::sync::mpsc::channel;
+    /// let (mut w, mut r) = keyvalue::new();
+    /// let (tx, rx) = channel();
+    /// r.set_on_change(move |change: &Change<u32, u32>| tx.send(change.clone()).unwrap());
+    /// w.insert(1, 2).unwrap();
+    /// w.remove(&1).unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(Change::Added { key: 1, value: 2 }, rx.recv().unwrap());
+    /// assert_eq!(Change::Removed { key: 1 }, rx.recv().unwrap());
+    /// ```
+    pub fn set_on_change<F>(&mut self, mut f: F)
+        where F: FnMut(&Change<K, V>) + Send + 'static
+    {
+        self.hooks.get_mut().observer = Some(Box::new(move |change: ChangeRef<'_, K, V>| {
+            if let Some(change) = change.to_change() {
+                f(&change)
+            }
+        }));
+    }
+
+    /// The changes that have arrived but haven't been applied yet, oldest first, without
+    /// applying them.  They stay queued and are applied by the next lookup or `refresh`
+    /// as usual.  Updates, deadlines and capacity hints aren't shown.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.remove(&1).unwrap();
+    /// assert_eq!(vec![Change::Added { key: 1, value: 1 }, Change::Removed { key: 1 }],
+    ///            r.peek_pending());
+    /// assert_eq!(2, r.pending_len());
+    /// assert_eq!(Some(0), r.len().ok());
+    /// assert!(r.peek_pending().is_empty());
+    /// ```
+    pub fn peek_pending(&self) -> Vec<Change<K, V>> {
+        self.inbox.stage();
+        let mut changes = Vec::new();
+        for (_, _, action) in self.inbox.staged.borrow().iter() {
+            action.changes(&mut changes);
+        }
+        changes
+    }
+
+    /// Applies every pending change, like `refresh`, and returns the changes in the order
+    /// they were applied.  These are the same changes `set_on_change` sees, so entries
+    /// dropped by `with_capacity_limit` are left out.  Once the writer is gone this fails
+    /// with `WriterGone`, but only after everything it sent has been returned.
+    ///
+    /// If a panic interrupts applying changes, such as one from the `Clone` impl of a
+    /// value, the change being applied may be lost, but the ones after it stay queued for
+    /// the next call.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::panic::{self, AssertUnwindSafe};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// static CLONES: AtomicUsize = AtomicUsize::new(3);
+    /// #[derive(Debug, PartialEq)]
+    /// struct Fragile(u32);
+    /// impl Clone for Fragile {
+    ///     fn clone(&self) -> Self {
+    ///         if CLONES.fetch_add(1, Ordering::SeqCst) == 2 {
+    ///             panic!("third clone");
+    ///         }
+    ///         Fragile(self.0)
+    ///     }
+    /// }
+    ///
+    /// let (mut w, r) = keyvalue::new();
+    /// for i in 0..5 {
+    ///     w.insert(i, Fragile(i)).unwrap();
+    /// }
+    /// CLONES.store(0, Ordering::SeqCst);
+    /// assert!(panic::catch_unwind(AssertUnwindSafe(|| r.drain_changes())).is_err());
+    /// assert_eq!(2, r.drain_changes().unwrap().len());
+    /// assert_eq!(Some(Fragile(4)), r.get(&4).unwrap());
+    /// ```
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// w.remove(&1).unwrap();
+    /// assert_eq!(vec![Change::Added { key: 1, value: 1 }, Change::Removed { key: 1 }],
+    ///            r.drain_changes().unwrap());
+    /// assert!(r.drain_changes().unwrap().is_empty());
+    /// drop(w);
+    /// assert!(r.drain_changes().is_err());
+    /// ```
+    pub fn drain_changes(&self) -> Result<Vec<Change<K, V>>> {
+        let mut changes = Vec::new();
+        self.apply_pending_with(&mut |change: &ChangeRef<'_, K, V>| {
+                                         changes.extend(change.to_change())
+                                     })?;
+        if changes.is_empty() {
+            self.inbox.check_open()?;
+        }
+        Ok(changes)
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        let value = self.hashmap.borrow().get(k).cloned();
+        self.hooks.borrow_mut().looked_up(k, value.is_some());
+        Ok(value)
+    }
+
+    /// `get` for a key the caller already owns, such as one built on the spot
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("k1".to_string(), 1).unwrap();
+    /// assert_eq!(Some(1), r.get_owned(format!("k{}", 1)).unwrap());
+    /// assert!(!r.contains_key_owned(format!("k{}", 2)).unwrap());
+    /// ```
+    pub fn get_owned(&self, k: K) -> Result<Option<V>> {
+        self.get(&k)
+    }
+
+    /// Like `get`, but if the key isn't present waits up to `timeout` for the writer to add
+    /// it, applying changes as they arrive.  Fails with `Timeout` if it still isn't there
+    /// by then, or with `WriterGone` if the writer goes away first.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// let (mut w, r) = keyvalue::new();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     w.insert("ready", true).unwrap();
+    ///     w.flush().unwrap();
+    /// });
+    /// assert!(r.wait_for("ready", Duration::from_millis(0)).unwrap_err().is_timeout());
+    /// assert_eq!(true, r.wait_for("ready", Duration::from_secs(10)).unwrap());
+    /// assert!(!r.wait_for("gone", Duration::from_secs(10)).unwrap_err().is_timeout());
+    /// ```
+    pub fn wait_for<Q>(&self, k: &Q, timeout: Duration) -> Result<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.apply_pending()?;
+            if let Some(v) = self.hashmap.borrow().get(k) {
+                return Ok(v.clone());
+            }
+            self.inbox.check_open()?;
+            if Instant::now() >= deadline {
+                return Err(Timeout.into());
+            }
+            self.inbox.wait_until(deadline);
+        }
+    }
+
+    /// Like `get`, returning `default` when the key isn't present
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("a", 1).unwrap();
+    /// assert_eq!(1, r.get_or("a", 0).unwrap());
+    /// assert_eq!(0, r.get_or("b", 0).unwrap());
+    /// assert_eq!(2, r.get_or_else("b", || 2).unwrap());
+    /// ```
+    pub fn get_or<Q>(&self, k: &Q, default: V) -> Result<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        Ok(self.get(k)?.unwrap_or(default))
+    }
+
+    /// Like `get`, returning what `f` makes when the key isn't present
+    pub fn get_or_else<Q, F>(&self, k: &Q, f: F) -> Result<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq,
+              F: FnOnce() -> V
+    {
+        Ok(self.get(k)?.unwrap_or_else(f))
+    }
+
+    /// Like `get`, but copies the value into `buf` with `clone_from`, so a buffer kept
+    /// between calls can reuse its allocation.  Returns whether the key was present,
+    /// leaving `buf` alone if it wasn't.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, vec![1, 2, 3]).unwrap();
+    /// let mut buf = Vec::with_capacity(16);
+    /// assert!(r.get_into(&1, &mut buf).unwrap());
+    /// assert_eq!(vec![1, 2, 3], buf);
+    /// assert!(!r.get_into(&2, &mut buf).unwrap());
+    /// ```
+    pub fn get_into<Q>(&self, k: &Q, buf: &mut V) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        let found = match self.hashmap.borrow().get(k) {
+            Some(v) => {
+                buf.clone_from(v);
+                true
+            }
+            None => false,
+        };
+        self.hooks.borrow_mut().looked_up(k, found);
+        Ok(found)
+    }
+
+    /// Looks up `k` in the state as of the last drain, without touching the channel.  Pair
+    /// with `refresh` to control when changes get applied.  From inside one of this
+    /// reader's callbacks there is no settled state to look in, so it finds nothing.
+    pub fn try_get<Q>(&self, k: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let (mut hooks, hashmap) = match (self.hooks.try_borrow_mut(), self.hashmap.try_borrow()) {
+            (Ok(hooks), Ok(hashmap)) => (hooks, hashmap),
+            _ => return None,
+        };
+        let value = hashmap.get(k).cloned();
+        hooks.looked_up(k, value.is_some());
+        value
+    }
+
+    /// Looks up several keys against the same settled state, draining the channel only
+    /// once.  Results are in the same order as `keys`.
+    pub fn get_many<Q>(&self, keys: &[&Q]) -> Result<Vec<Option<V>>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        let mut hooks = self.hooks.borrow_mut();
+        let hashmap = self.hashmap.borrow();
+        Ok(keys.iter()
+               .map(|k| {
+                        let value = hashmap.get(*k).cloned();
+                        hooks.looked_up(*k, value.is_some());
+                        value
+                    })
+               .collect())
+    }
+
+    /// `get_many` for exactly two keys, without allocating
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("a", 1).unwrap();
+    /// assert_eq!((Some(1), None), r.get_pair("a", "b").unwrap());
+    /// ```
+    pub fn get_pair<Q>(&self, a: &Q, b: &Q) -> Result<(Option<V>, Option<V>)>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        let mut hooks = self.hooks.borrow_mut();
+        let hashmap = self.hashmap.borrow();
+        let mut get = |k: &Q| {
+            let value = hashmap.get(k).cloned();
+            hooks.looked_up(k, value.is_some());
+            value
+        };
+        Ok((get(a), get(b)))
+    }
+
+    /// Returns a point-in-time copy of every entry the reader holds.  Writes made after
+    /// this call won't be reflected until it is called again.
+    pub fn iter(&self) -> Result<Vec<(K, V)>> {
+        self.process_changes()?;
+        Ok(self.hashmap
+               .borrow()
+               .iter()
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+
+    /// Point-in-time copy of the entries for which `f` returns true, see `iter`
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("user:1", 1).unwrap();
+    /// w.insert("user:2", 2).unwrap();
+    /// w.insert("group:1", 3).unwrap();
+    /// let mut users = r.filter(|k, _| k.starts_with("user:")).unwrap();
+    /// users.sort();
+    /// assert_eq!(vec![("user:1", 1), ("user:2", 2)], users);
+    /// ```
+    pub fn filter<F: Fn(&K, &V) -> bool>(&self, f: F) -> Result<Vec<(K, V)>> {
+        self.process_changes()?;
+        Ok(self.hashmap
+               .borrow()
+               .iter()
+               .filter(|&(k, v)| f(k, v))
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+
+    /// Point-in-time copy of the values, see `iter`
+    pub fn values(&self) -> Result<Vec<V>> {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().values().cloned().collect())
+    }
+
+    /// Returns an owned copy of the reader's map, built with the same hasher.  It is
+    /// detached from the reader, so later writes won't show up in it.
+    pub fn snapshot(&self) -> Result<HashMap<K, V, S>>
+        where S: Clone
+    {
+        self.process_changes()?;
+        Ok(self.hashmap.borrow().clone())
+    }
+
+    /// Applies pending changes, then lists what would turn `other` into a copy of this
+    /// reader: an add for each key that is new or holds a different value here, and a
+    /// remove for each key only `other` has.  Entries the two agree on aren't cloned.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// use std::collections::HashMap;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.extend(vec![(1, 1), (2, 20), (4, 4)]).unwrap();
+    /// let store: HashMap<_, _> = vec![(1, 1), (2, 2), (3, 3)].into_iter().collect();
+    /// let mut changes = r.diff(&store).unwrap();
+    /// changes.sort_by_key(|c| match *c {
+    ///     Change::Added { key, .. } | Change::Removed { key } => key,
+    ///     Change::Cleared => 0,
+    /// });
+    /// assert_eq!(vec![Change::Added { key: 2, value: 20 },
+    ///                 Change::Removed { key: 3 },
+    ///                 Change::Added { key: 4, value: 4 }],
+    ///            changes);
+    ///
+    /// let (mut stale, _reader) = keyvalue::from_iter(store);
+    /// stale.apply_changes(changes).unwrap();
+    /// assert!(r.eq_writer(&stale).unwrap());
+    /// ```
+    pub fn diff<T: BuildHasher>(&self, other: &HashMap<K, V, T>) -> Result<Vec<Change<K, V>>>
+        where V: PartialEq
+    {
+        self.process_changes()?;
+        let hashmap = self.hashmap.borrow();
+        let added = hashmap
+            .iter()
+            .filter(|&(k, v)| other.get(k) != Some(v))
+            .map(|(k, v)| {
+                     Change::Added {
+                         key: k.clone(),
+                         value: v.clone(),
+                     }
+                 });
+        let removed = other
+            .keys()
+            .filter(|k| !hashmap.contains_key(*k))
+            .map(|k| Change::Removed { key: k.clone() });
+        Ok(added.chain(removed).collect())
+    }
+}
+
+/// Shows the last settled state without applying anything still pending on the channel
+impl<K: Debug, V: Debug, S> Debug for ReadOnlyHashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("ReadOnlyHashMap");
+        match self.hashmap.try_borrow() {
+            Ok(hashmap) => d.field("hashmap", &*hashmap),
+            Err(_) => d.field("hashmap", &"<borrowed>"),
+        };
+        d.field("pending", &self.inbox.pending.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+/// Serializes the reader's contents after applying any pending changes
+#[cfg(feature = "serde")]
+impl<K, V, S> Serialize for ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Serialize,
+          V: Serialize,
+          S: BuildHasher
+{
+    fn serialize<Se>(&self, serializer: Se) -> ::std::result::Result<Se::Ok, Se::Error>
+        where Se: Serializer
+    {
+        self.process_changes().map_err(|e| ser::Error::custom(e.to_string()))?;
+        self.hashmap.borrow().serialize(serializer)
+    }
+}
+
+pub struct WritableHashMap<K, V, S = RandomState> {
+    hashmap: HashMap<K, V, S>,
+    outbox: Outbox<K, V>,
+    expiries: Expiries<K>,
+    evictions: Option<EvictionNotices<K>>,
+}
+
+
+impl<K, V, S> WritableHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    fn from_map(hashmap: HashMap<K, V, S>, outbox: Outbox<K, V>) -> Self {
+        WritableHashMap {
+            hashmap,
+            outbox,
+            expiries: Expiries::new(),
+            evictions: None,
+        }
+    }
+
+    /// Removes every entry.  Like `HashMap::clear`, both the writer and the readers keep
+    /// the memory they had allocated, ready to be refilled.  If the clear can't be
+    /// delivered the local map is left unchanged, as with `insert`.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// drop(r);
+    /// assert!(w.clear().is_err());
+    /// assert_eq!(Some(&1), w.get(&1));
+    /// ```
+    pub fn clear(&mut self) -> Result<()> {
+        self.clear_with(true)
+    }
+
+    /// Like `clear`, but the readers also make room for as many entries as the writer's map
+    /// has capacity for, even if theirs had been shrunk or never grown that far.  For
+    /// refilling to a similar size straight after without any of the maps growing step by
+    /// step, at the cost of holding on to the memory until then.  `clear_and_shrink` makes
+    /// the opposite trade.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new::<u32, u32>();
+    /// w.reserve(1000);
+    /// assert!(r.capacity().unwrap() < 1000);
+    /// w.clear_and_keep_capacity().unwrap();
+    /// assert!(r.is_empty().unwrap());
+    /// assert!(r.capacity().unwrap() >= 1000);
+    /// ```
+    pub fn clear_and_keep_capacity(&mut self) -> Result<()> {
+        let additional = self.hashmap.capacity();
+        self.send(Action::ClearKeepCapacity(additional), true)?;
+        self.hashmap.clear();
+        Ok(())
+    }
+
+    /// Like `clear`, but the writer and every reader also hand back the memory their maps
+    /// had allocated, for after a large map is emptied for good.  Unlike `shrink_to_fit`,
+    /// the readers shrink whether or not `with_capacity_hints` is set.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.extend((0..1000).map(|i| (i, i))).unwrap();
+    /// assert!(r.capacity().unwrap() >= 1000);
+    /// w.clear_and_shrink().unwrap();
+    /// assert_eq!(0, r.capacity().unwrap());
+    /// assert_eq!(0, w.capacity());
+    /// ```
+    pub fn clear_and_shrink(&mut self) -> Result<()> {
+        self.send(Action::Batch(vec![Action::Clear, Action::Shrink]), true)?;
+        self.hashmap.clear();
+        self.hashmap.shrink_to_fit();
+        Ok(())
+    }
+
+    /// Like `clear`, but fails with `Full` instead of blocking on a bounded channel
+    pub fn clear_nonblocking(&mut self) -> Result<()> {
+        self.clear_with(false)
+    }
+
+    fn clear_with(&mut self, block: bool) -> Result<()> {
+        self.send(Action::Clear, block)
+            .map(|_| self.hashmap.clear())
+    }
+
+    /// Empties the map, handing back everything it held.  Readers are sent a single clear
+    /// rather than a removal per entry.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// assert_eq!(vec![(1, 1)], w.drain().unwrap());
+    /// assert!(w.is_empty());
+    /// assert!(r.is_empty().unwrap());
+    /// ```
+    pub fn drain(&mut self) -> Result<Vec<(K, V)>> {
+        self.send(Action::Clear, true)?;
+        Ok(self.hashmap.drain().collect())
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.hashmap.get(k)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.hashmap.contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashmap.is_empty()
+    }
+
+    /// Capacity of the writer's local map
+    pub fn capacity(&self) -> usize {
+        self.hashmap.capacity()
+    }
+
+    /// The hasher the writer's local map was built with
+    pub fn hasher(&self) -> &S {
+        self.hashmap.hasher()
+    }
+
+    /// Hands back the writer's local map, disconnecting it from its readers as dropping
+    /// it would
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// assert_eq!(Some(&1), w.into_inner().get(&1));
+    /// assert!(r.refresh().is_err());
+    /// ```
+    pub fn into_inner(self) -> HashMap<K, V, S> {
+        self.hashmap
+    }
+
+    /// Makes `reserve` and `shrink_to_fit` pass the same request on to the readers, so
+    /// their memory use follows the writer's.  The requests are only hints: they are
+    /// dropped rather than waited on when a bounded channel is full, and while coalescing.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (w, r) = keyvalue::new::<u32, u32>();
+    /// let mut w = w.with_capacity_hints();
+    /// w.reserve(100);
+    /// assert!(r.capacity().unwrap() >= 100);
+    /// w.shrink_to_fit();
+    /// assert_eq!(0, r.capacity().unwrap());
+    /// ```
+    pub fn with_capacity_hints(mut self) -> Self {
+        self.outbox.capacity_hints = true;
+        self
+    }
+
+    /// Has readers skip removing a key that a batch of changes, such as a `transaction`,
+    /// goes on to add again, so they see a single add with the same end result.  A clear,
+    /// or anything else done to the key in between, keeps the removal.  Readers using
+    /// `with_merge_policy` would merge into the old value instead of replacing it, and
+    /// ones using `with_insertion_order` keep the key in its old place, so this isn't for
+    /// them.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// use std::sync::mpsc::channel;
+    /// let (w, mut r) = keyvalue::from_iter(vec![("a", 0), ("b", 0)]);
+    /// let mut w = w.with_readd_collapsing();
+    /// let (tx, rx) = channel();
+    /// r.set_on_change(move |change: &Change<&str, u32>| tx.send(change.clone()).unwrap());
+    /// w.transaction(|t| {
+    ///     t.remove("a");
+    ///     t.insert("b", 1);
+    ///     t.insert("a", 1);
+    ///     Ok(())
+    /// }).unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(vec![Change::Added { key: "b", value: 1 }, Change::Added { key: "a", value: 1 }],
+    ///            rx.try_iter().collect::<Vec<_>>());
+    /// w.transaction(|t| {
+    ///     t.remove("a");
+    ///     t.clear();
+    ///     t.insert("a", 2);
+    ///     Ok(())
+    /// }).unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(3, rx.try_iter().count());
+    /// assert!(r.eq_writer(&w).unwrap());
+    /// ```
+    pub fn with_readd_collapsing(mut self) -> Self {
+        self.outbox.collapse_readds = true;
+        self
+    }
+
+    /// Reserves room for at least `additional` more entries in the local map, and in the
+    /// readers' if `with_capacity_hints` is set
+    pub fn reserve(&mut self, additional: usize) {
+        self.hashmap.reserve(additional);
+        self.hint(Action::Reserve(additional));
+    }
+
+    /// Shrinks the local map as much as possible, and the readers' if
+    /// `with_capacity_hints` is set
+    pub fn shrink_to_fit(&mut self) {
+        self.hashmap.shrink_to_fit();
+        self.hint(Action::Shrink);
+    }
+
+    fn hint(&mut self, action: Action<K, V>) {
+        if self.outbox.capacity_hints {
+            let _ = self.send(action, false);
+        }
+    }
+
+    /// Inserts a pair, returning the previous value for the key.  The pair is sent before
+    /// it's stored, so if it can't be delivered it is handed back in the error and the
+    /// local map is left unchanged.
+    ///
+    /// ```
+    /// use ecds::errors::ErrorKind;
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("key", "old").unwrap();
+    /// drop(r);
+    /// let e = w.insert("key", "value").unwrap_err();
+    /// assert_eq!(&ErrorKind::Disconnected, e.kind());
+    /// assert_eq!(("key", "value"), e.into_inner());
+    /// assert_eq!(Some(&"old"), w.get("key"));
+    /// let e = w.insert("other", "value").unwrap_err();
+    /// assert!(e.is_disconnected());
+    /// assert!(!w.contains_key("other"));
+    /// assert_eq!(1, w.len());
+    /// ```
+    pub fn insert(&mut self, k: K, v: V) -> InsertResult<Option<V>, K, V> {
+        self.insert_with(k, v, true)
+    }
+
+    /// Inserts a pair only if the key isn't present yet, sending nothing otherwise.  The
+    /// outer `Result` fails as `insert` does when the pair can't be delivered, leaving the
+    /// local map unchanged.  The inner one is `Ok` with the newly stored value, or an
+    /// `OccupiedError` with the value already there and `v` handed back.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// assert_eq!(&1, w.try_insert("a", 1).unwrap().unwrap());
+    /// let e = w.try_insert("a", 2).unwrap().unwrap_err();
+    /// assert_eq!((&1, 2), (e.current, e.value));
+    /// assert_eq!(1, w.version());
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// ```
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<::std::result::Result<&V, OccupiedError<'_, V>>> {
+        if self.hashmap.contains_key(&k) {
+            return Ok(Err(OccupiedError {
+                              current: &self.hashmap[&k],
+                              value: v,
+                          }));
+        }
+        self.send(Action::Add(k.clone(), v.clone()), true)?;
+        Ok(Ok(self.hashmap.entry(k).or_insert(v)))
+    }
+
+    /// `try_insert` for callers that only need to know whether the key was claimed.  This
+    /// only guards against this writer's own earlier inserts, as seen in its local map;
+    /// it is not a lock shared with other writers, such as a `clone_writer`.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// assert!(w.insert_if_absent("leader", 1).unwrap());
+    /// assert!(!w.insert_if_absent("leader", 2).unwrap());
+    /// assert_eq!(Some(1), r.get("leader").unwrap());
+    /// ```
+    pub fn insert_if_absent(&mut self, k: K, v: V) -> Result<bool> {
+        Ok(self.try_insert(k, v)?.is_ok())
+    }
+
+    /// Like `insert`, but fails with `Full` instead of blocking on a bounded channel.  A
+    /// reader that has been dropped doesn't count, however full it was left.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::bounded(1);
+    /// w.insert_nonblocking(1, 1).unwrap();
+    /// drop(r);
+    /// assert!(w.insert_nonblocking(2, 2).unwrap_err().is_disconnected());
+    /// ```
+    pub fn insert_nonblocking(&mut self, k: K, v: V) -> InsertResult<Option<V>, K, V> {
+        self.insert_with(k, v, false)
+    }
+
+    fn insert_with(&mut self, k: K, v: V, block: bool) -> InsertResult<Option<V>, K, V> {
+        match self.send(Action::Add(k.clone(), v.clone()), block) {
+            Ok(()) => Ok(self.hashmap.insert(k, v)),
+            Err(error) => {
+                Err(InsertError {
+                        key: k,
+                        value: v,
+                        error,
+                    })
+            }
+        }
+    }
+
+    /// Removes a key, shipping the owned copy held locally to the readers.  Nothing is sent
+    /// when the key isn't present.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// assert_eq!(None, w.remove(&2).unwrap());
+    /// assert_eq!(0, r.pending_len());
+    /// assert_eq!(Some(1), w.remove(&1).unwrap());
+    /// assert_eq!(1, r.pending_len());
+    /// ```
+    pub fn remove<Q>(&mut self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.remove_with(k, true)
+    }
+
+    /// Another name for `remove`, for callers after the owned value
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(String::from("a"), vec![1, 2, 3]).unwrap();
+    /// assert_eq!(Some(vec![1, 2, 3]), w.take("a").unwrap());
+    /// assert_eq!(None, w.take("a").unwrap());
+    /// assert_eq!(2, w.version());
+    /// assert!(r.is_empty().unwrap());
+    /// ```
+    pub fn take<Q>(&mut self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.remove(k)
+    }
+
+    /// Removes every key in `keys`, returning their values in the same order.  The keys
+    /// that were present reach the readers as a single message; nothing is sent if none
+    /// were.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a", 1), ("b", 2), ("c", 3)]);
+    /// assert_eq!(vec![Some(3), None, Some(1)], w.remove_many(&["c", "d", "a"]).unwrap());
+    /// assert_eq!(1, w.version());
+    /// assert_eq!(vec!["b"], r.keys().unwrap());
+    /// ```
+    pub fn remove_many<'a, Q, I>(&mut self, keys: I) -> Result<Vec<Option<V>>>
+        where K: Borrow<Q>,
+              Q: 'a + ?Sized + Hash + Eq,
+              I: IntoIterator<Item = &'a Q>
+    {
+        let keys: Vec<&Q> = keys.into_iter().collect();
+        let present: HashSet<K> = keys.iter()
+            .filter_map(|k| self.hashmap.get_key_value(*k))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !present.is_empty() {
+            self.send(Action::RemoveMany(present.into_iter().collect()), true)?;
+        }
+        Ok(keys.into_iter().map(|k| self.hashmap.remove(k)).collect())
+    }
+
+    /// Like `remove`, but fails with `Full` instead of blocking on a bounded channel
+    pub fn remove_nonblocking<Q>(&mut self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.remove_with(k, false)
+    }
+
+    fn remove_with<Q>(&mut self, k: &Q, block: bool) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let key = match self.hashmap.get_key_value(k) {
+            Some((key, _)) => key.clone(),
+            None => return Ok(None),
+        };
+        self.send(Action::Remove(key), block)
+            .map(|_| self.hashmap.remove(k))
+    }
+
+    /// Inserts every pair from `iter`, shipping the whole batch to the readers as a single
+    /// message.  Pairs are applied in order, so if a key repeats its last value wins, as
+    /// with separate inserts.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(0, 0)]);
+    /// w.extend(vec![(1, 1), (2, 2), (1, 3)]).unwrap();
+    /// w.clear().unwrap();
+    /// w.extend(vec![(2, 4), (2, 5)]).unwrap();
+    /// assert_eq!(3, r.pending_len());
+    /// assert_eq!(vec![(2, 5)], r.iter().unwrap());
+    /// w.extend(vec![(1, 1), (1, 6)]).unwrap();
+    /// assert_eq!(Some(6), r.get(&1).unwrap());
+    /// assert_eq!(Some(&6), w.get(&1));
+    /// ```
+    pub fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<()> {
+        let pairs: Vec<(K, V)> = iter.into_iter().collect();
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        self.send(Action::AddMany(pairs.clone()), true)?;
+        self.hashmap.extend(pairs);
+        Ok(())
+    }
+
+    /// `extend` for values that are `Copy`, which are copied into the message rather than
+    /// cloned.  Keys are still cloned.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.extend_copy((0..100u32).map(|i| (i, i as f64))).unwrap();
+    /// assert_eq!(1, r.pending_len());
+    /// assert_eq!(Some(99.0), r.get(&99).unwrap());
+    /// ```
+    pub fn extend_copy<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<()>
+        where V: Copy
+    {
+        let pairs: Vec<(K, V)> = iter.into_iter().collect();
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        self.send(Action::AddMany(pairs.iter().map(|(k, v)| (k.clone(), *v)).collect()), true)?;
+        self.hashmap.extend(pairs);
+        Ok(())
+    }
+
+    /// Like `extend`, but first makes room for every pair in the writer's map and in the
+    /// readers', so a large initial load doesn't grow either map step by step
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.load_from((0..1000).map(|i| (i, i))).unwrap();
+    /// assert!(w.capacity() >= 1000);
+    /// assert_eq!(1, r.pending_len());
+    /// assert!(r.capacity().unwrap() >= 1000);
+    /// assert_eq!(Some(999), r.get(&999).unwrap());
+    /// ```
+    pub fn load_from<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<()> {
+        let pairs: Vec<(K, V)> = iter.into_iter().collect();
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        self.send(Action::Batch(vec![Action::Reserve(pairs.len()),
+                                     Action::AddMany(pairs.clone())]),
+                  true)?;
+        self.hashmap.reserve(pairs.len());
+        self.hashmap.extend(pairs);
+        Ok(())
+    }
+
+    /// Inserts every pair from `other`, replacing existing values, see `extend`
+    pub fn merge(&mut self, other: HashMap<K, V>) -> Result<()> {
+        self.extend(other)
+    }
+
+    /// Like `merge`, but a key already present gets `f(old, new)` instead of `new`
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::collections::HashMap;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a", 1)]);
+    /// let other: HashMap<_, _> = vec![("a", 2), ("b", 3)].into_iter().collect();
+    /// w.merge_with(other, |old, new| old + new).unwrap();
+    /// assert_eq!(Some(3), r.get("a").unwrap());
+    /// assert_eq!(Some(3), r.get("b").unwrap());
+    /// ```
+    pub fn merge_with<F: Fn(&V, V) -> V>(&mut self, other: HashMap<K, V>, f: F) -> Result<()> {
+        let pairs: Vec<(K, V)> = other.into_iter()
+            .map(|(k, v)| {
+                     let v = match self.hashmap.get(&k) {
+                         Some(old) => f(old, v),
+                         None => v,
+                     };
+                     (k, v)
+                 })
+            .collect();
+        self.extend(pairs)
+    }
+
+    /// Keeps only the entries for which `f` returns true.  The dropped keys reach the
+    /// readers as one message, and nothing is sent if every entry is kept.
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&mut self, mut f: F) -> Result<()> {
+        let dropped: Vec<K> = self.hashmap
+            .iter()
+            .filter(|&(k, v)| !f(k, v))
+            .map(|(k, _)| k.clone())
+            .collect();
+        if dropped.is_empty() {
+            return Ok(());
+        }
+        self.send(Action::RemoveMany(dropped.clone()), true)?;
+        for k in dropped {
+            self.hashmap.remove(&k);
+        }
+        Ok(())
+    }
+
+    /// Swaps the values of two keys, returning `false` and sending nothing unless both are
+    /// present.  Readers get both new values in one message, so they never see just one
+    /// of them swapped.  Neither entry keeps a time to live it had.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a", 1), ("b", 2)]);
+    /// assert!(w.swap("a", "b").unwrap());
+    /// assert!(!w.swap("a", "c").unwrap());
+    /// assert_eq!(1, w.version());
+    /// assert_eq!(vec![Some(2), Some(1)], r.get_many(&["a", "b"]).unwrap());
+    /// ```
+    pub fn swap<Q>(&mut self, a: &Q, b: &Q) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        let ((ka, va), (kb, vb)) = match (self.hashmap.get_key_value(a),
+                                          self.hashmap.get_key_value(b)) {
+            (Some((ka, va)), Some((kb, vb))) => {
+                ((ka.clone(), va.clone()), (kb.clone(), vb.clone()))
+            }
+            _ => return Ok(false),
+        };
+        self.send(Action::Batch(vec![Action::Add(ka.clone(), vb.clone()),
+                                     Action::Add(kb.clone(), va.clone())]),
+                  true)?;
+        self.hashmap.insert(ka, vb);
+        self.hashmap.insert(kb, va);
+        Ok(true)
+    }
+
+    /// Sends the writer's whole local map to the readers, which drop what they hold and
+    /// start over from it.  Useful to bring a reader back in step after it missed changes,
+    /// for instance when `net::connect` was cut off.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// w.resync().unwrap();
+    /// assert_eq!(1, r.pending_len());
+    /// assert_eq!(vec![(1, 1)], r.iter().unwrap());
+    /// ```
+    pub fn resync(&mut self) -> Result<()> {
+        let pairs = self.hashmap
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let mut batch = vec![Action::Replace(pairs)];
+        batch.extend(self.hashmap
+                         .keys()
+                         .filter_map(|k| {
+                                         self.expiries
+                                             .deadline(k)
+                                             .map(|at| Action::Expire(k.clone(), at))
+                                     }));
+        let action = if batch.len() == 1 {
+            batch.remove(0)
+        } else {
+            Action::Batch(batch)
+        };
+        self.send(action, true)
+    }
+
+    /// Replaces every key with what `f` makes of it, keeping values and times to live.  The
+    /// readers are sent the renamed map as one message, like `resync`.  If `f` gives two
+    /// keys the same new name, only one of their entries is kept.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a".to_string(), 1)]);
+    /// w.rekey(|k| format!("tenant/{}", k)).unwrap();
+    /// assert_eq!(Some(&1), w.get("tenant/a"));
+    /// assert_eq!(vec![("tenant/a".to_string(), 1)], r.iter().unwrap());
+    /// ```
+    pub fn rekey<F: Fn(&K) -> K>(&mut self, f: F) -> Result<()> {
+        let mut pairs = Vec::with_capacity(self.hashmap.len());
+        let mut deadlines = Vec::new();
+        for (k, v) in &self.hashmap {
+            let key = f(k);
+            if let Some(at) = self.expiries.deadline(k) {
+                deadlines.push(Action::Expire(key.clone(), at));
+            }
+            pairs.push((key, v.clone()));
+        }
+        let replace = Action::Replace(pairs.clone());
+        let action = if deadlines.is_empty() {
+            replace
+        } else {
+            deadlines.insert(0, replace);
+            Action::Batch(deadlines)
+        };
+        self.send(action, true)?;
+        self.hashmap.clear();
+        self.hashmap.extend(pairs);
+        Ok(())
+    }
+
+    /// Makes each of `changes` in turn, sending them all to the readers as one message.
+    /// Together with `ReadOnlyHashMap::drain_changes` this can copy changes from one pair
+    /// to another.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut upstream, source) = keyvalue::new();
+    /// upstream.insert(1, 1).unwrap();
+    /// upstream.insert(2, 2).unwrap();
+    /// upstream.remove(&1).unwrap();
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 0)]);
+    /// w.apply_changes(source.drain_changes().unwrap()).unwrap();
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// assert_eq!(1, w.len());
+    /// ```
+    pub fn apply_changes<I: IntoIterator<Item = Change<K, V>>>(&mut self, changes: I) -> Result<()> {
+        let actions: Vec<_> = changes.into_iter().map(Action::from).collect();
+        if actions.is_empty() {
+            return Ok(());
+        }
+        self.send(Action::Batch(actions.clone()), true)?;
+        Action::Batch(actions).apply_to(&mut self.hashmap, &mut |_| ());
+        Ok(())
+    }
+
+    /// Replaces every value with what `f` makes of it.  The new values reach the readers as
+    /// one message, so they go straight from the old state to the transformed one.  Entries
+    /// keep any time to live they had.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a", 1), ("b", 2)]);
+    /// w.map_values(|_, v| v * 10).unwrap();
+    /// assert_eq!(Some(20), r.get("b").unwrap());
+    /// assert_eq!(Some(&10), w.get("a"));
+    /// ```
+    pub fn map_values<F: Fn(&K, &V) -> V>(&mut self, f: F) -> Result<()> {
+        let pairs: Vec<(K, V)> = self.hashmap
+            .iter()
+            .map(|(k, v)| (k.clone(), f(k, v)))
+            .collect();
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let mut deadlines: Vec<Action<K, V>> = pairs.iter()
+            .filter_map(|(k, _)| {
+                            self.expiries
+                                .deadline(k)
+                                .map(|at| Action::Expire(k.clone(), at))
+                        })
+            .collect();
+        let action = if deadlines.is_empty() {
+            Action::AddMany(pairs.clone())
+        } else {
+            deadlines.insert(0, Action::AddMany(pairs.clone()));
+            Action::Batch(deadlines)
+        };
+        self.send(action, true)?;
+        self.hashmap.extend(pairs);
+        Ok(())
+    }
+
+    /// Applies `delta` to the value stored under `k` and ships only the delta to the
+    /// readers.  Returns false, sending nothing, if the key isn't present.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("counter", 1).unwrap();
+    /// assert!(w.update("counter", |v: &mut i32| *v += 5).unwrap());
+    /// assert!(!w.update("missing", |v: &mut i32| *v += 5).unwrap());
+    /// assert_eq!(Some(6), r.get("counter").unwrap());
+    /// ```
+    pub fn update<Q, D>(&mut self, k: &Q, delta: D) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq,
+              D: ApplyDelta<V> + Send + Sync + 'static
+    {
+        let key = match self.hashmap.get_key_value(k) {
+            Some((key, _)) => key.clone(),
+            None => return Ok(false),
+        };
+        if self.outbox.wal.is_some() {
+            // A delta can't be written to the log, so send the value it produces instead
+            let mut v = self.hashmap[k].clone();
+            delta.apply(&mut v);
+            self.send(Action::Add(key, v.clone()), true)?;
+            if let Some(slot) = self.hashmap.get_mut(k) {
+                *slot = v;
+            }
+            return Ok(true);
+        }
+        let delta = Arc::new(delta);
+        self.send(Action::Update(key, delta.clone()), true)?;
+        if let Some(v) = self.hashmap.get_mut(k) {
+            delta.apply(v);
+        }
+        Ok(true)
+    }
+
+    /// Returns a guard for changing the value under `k` in place.  If the value is borrowed
+    /// mutably through the guard, the result is published to the readers when the guard is
+    /// dropped.  Fails up front if no readers are left to publish to.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("list", vec![1]).unwrap();
+    /// w.get_mut("list").unwrap().unwrap().push(2);
+    /// assert_eq!(Some(vec![1, 2]), r.get("list").unwrap());
+    /// ```
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Result<Option<GuardedMut<'_, K, V, S>>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        if self.outbox.txs.is_empty() {
+            return Err(Disconnected.into());
+        }
+        let key = match self.hashmap.get_key_value(k) {
+            Some((key, _)) => key.clone(),
+            None => return Ok(None),
+        };
+        Ok(Some(GuardedMut {
+                    key,
+                    writer: self,
+                    changed: false,
+                }))
+    }
+
+    /// Applies a change taken from a reader's change stream, as if it had been made
+    /// directly on this map
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// let (mut w, r) = keyvalue::new();
+    /// w.apply(Change::Added { key: 1, value: 2 }).unwrap();
+    /// w.apply(Change::Added { key: 3, value: 4 }).unwrap();
+    /// w.apply(Change::Removed { key: 1 }).unwrap();
+    /// assert_eq!(None, r.get(&1).unwrap());
+    /// assert_eq!(Some(4), r.get(&3).unwrap());
+    /// ```
+    pub fn apply(&mut self, change: Change<K, V>) -> Result<()> {
+        if let Change::Removed { ref key } = change {
+            if !self.hashmap.contains_key(key) {
+                return Ok(());
+            }
+        }
+        let action = Action::from(change);
+        self.send(action.clone(), true)?;
+        action.apply_to(&mut self.hashmap, &mut |_| ());
+        Ok(())
+    }
+
+    /// Runs `f` to stage a set of changes, then applies them locally and ships them as one
+    /// message so readers see either all of them or none.  If `f` fails nothing is sent and
+    /// the local map is left untouched.  As with `remove`, removing a key that isn't there
+    /// by that point sends nothing.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.transaction(|t| {
+    ///     t.insert("from", 0);
+    ///     t.insert("to", 100);
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert!(w.transaction(|t| {
+    ///     t.remove("to");
+    ///     Err("abort".into())
+    /// }).is_err());
+    /// assert_eq!(Some(100), r.get("to").unwrap());
+    /// w.transaction(|t| {
+    ///     t.remove("missing");
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert_eq!(1, w.version());
+    /// ```
+    pub fn transaction<F>(&mut self, f: F) -> Result<()>
+        where F: FnOnce(&mut Transaction<K, V>) -> Result<()>
+    {
+        let mut transaction = Transaction { actions: Vec::new() };
+        f(&mut transaction)?;
+        // Whether each key touched so far will be present, for telling which removes matter
+        let hashmap = &self.hashmap;
+        let mut present = HashMap::new();
+        let mut cleared = false;
+        transaction.actions
+            .retain(|action| match *action {
+                        Action::Add(ref k, _) => {
+                            present.insert(k.clone(), true);
+                            true
+                        }
+                        Action::Remove(ref k) => {
+                            present.insert(k.clone(), false)
+                                .unwrap_or_else(|| !cleared && hashmap.contains_key(k))
+                        }
+                        Action::Clear => {
+                            cleared = true;
+                            present.clear();
+                            true
+                        }
+                        _ => true,
+                    });
+        if transaction.actions.is_empty() {
+            return Ok(());
+        }
+        let batch = Action::Batch(transaction.actions);
+        self.send(batch.clone(), true)?;
+        batch.apply_to(&mut self.hashmap, &mut |_| ());
+        Ok(())
+    }
+
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        Entry {
+            writer: self,
+            key,
+            changed: false,
+        }
+    }
+
+    /// Like `entry`, but takes the key by reference and only converts it into an owned key
+    /// when it has to be inserted
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new::<String, u32>();
+    /// assert_eq!(&1, w.entry_ref("a").or_insert(1).unwrap());
+    /// assert_eq!(&1, w.entry_ref("a").or_insert(2).unwrap());
+    /// assert_eq!(1, w.version());
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// ```
+    pub fn entry_ref<'q, Q>(&mut self, k: &'q Q) -> EntryRef<'_, 'q, K, V, Q, S>
+        where K: Borrow<Q> + From<&'q Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        EntryRef {
+            writer: self,
+            key: k,
+        }
+    }
+
+    /// Returns the value for `k`, first computing it with `f` and publishing it if the key
+    /// isn't present.  Nothing is sent when it is.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// assert_eq!(&1, w.get_or_insert_with("a", || 1).unwrap());
+    /// assert_eq!(&1, w.get_or_insert_with("a", || 2).unwrap());
+    /// assert_eq!(1, w.version());
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// ```
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, k: K, f: F) -> Result<&V> {
+        self.entry(k).or_insert_with(f)
+    }
+
+    /// Inserts `new` only if the writer's current value for `k` matches `expected`, with
+    /// `None` meaning the key must be absent.  Returns whether it was inserted, sending
+    /// nothing when it wasn't.  The check is against the writer's own map, never a
+    /// reader's possibly stale copy.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// assert!(w.compare_and_swap("a", None, 1).unwrap());
+    /// assert!(!w.compare_and_swap("a", None, 2).unwrap());
+    /// assert!(!w.compare_and_swap("a", Some(&2), 3).unwrap());
+    /// assert!(w.compare_and_swap("a", Some(&1), 3).unwrap());
+    /// assert_eq!(Some(3), r.get("a").unwrap());
+    /// ```
+    pub fn compare_and_swap(&mut self, k: K, expected: Option<&V>, new: V) -> Result<bool>
+        where V: PartialEq
+    {
+        if self.hashmap.get(&k) != expected {
+            return Ok(false);
+        }
+        self.insert(k, new)?;
+        Ok(true)
+    }
+
+    /// Same as `insert`, for call sites overwriting a value they expect to be there
+    pub fn replace(&mut self, k: K, v: V) -> Result<Option<V>> {
+        Ok(self.insert(k, v)?)
+    }
+
+    /// Like `replace`, but only if the key is already present.  Nothing is sent when it
+    /// isn't.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, "a")]);
+    /// assert_eq!(Some("a"), w.replace_if_present(1, "b").unwrap());
+    /// assert_eq!(None, w.replace_if_present(2, "b").unwrap());
+    /// assert_eq!(vec![Some("b"), None], r.get_many(&[&1, &2]).unwrap());
+    /// ```
+    pub fn replace_if_present(&mut self, k: K, v: V) -> Result<Option<V>> {
+        if !self.hashmap.contains_key(&k) {
+            return Ok(None);
+        }
+        self.replace(k, v)
+    }
+
+    /// Creates another writer feeding the same readers, starting from a copy of this
+    /// writer's local map.  Each writer only ever sees its own changes locally, while the
+    /// readers see every writer's changes in the order they arrive on the channel, so the
+    /// last write to reach a reader wins.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut a, r) = keyvalue::new();
+    /// a.insert(1, "a").unwrap();
+    /// let mut b = a.clone_writer();
+    /// b.insert(2, "b").unwrap();
+    /// assert!(!a.contains_key(&2));
+    /// assert_eq!(vec![Some("a"), Some("b")], r.get_many(&[&1, &2]).unwrap());
+    /// ```
+    pub fn clone_writer(&self) -> Self
+        where S: Clone
+    {
+        WritableHashMap::from_map(self.hashmap.clone(), self.outbox.clone())
+    }
+
+    /// Creates an independent pair that both start out holding a copy of this writer's
+    /// local map.  Unlike `clone_writer`, the new pair has its own channel: changes made
+    /// through this writer never reach it, and changes made through it never reach this
+    /// writer's readers.  Times to live aren't carried over.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// let (mut fw, fr) = w.fork();
+    /// w.insert(2, 2).unwrap();
+    /// fw.insert(3, 3).unwrap();
+    /// assert_eq!(Some(1), fr.get(&1).unwrap());
+    /// assert_eq!(None, fr.get(&2).unwrap());
+    /// assert_eq!(None, r.get(&3).unwrap());
+    /// ```
+    pub fn fork(&self) -> (Self, ReadOnlyHashMap<K, V, S>)
+        where S: Clone
+    {
+        seeded(self.hashmap.clone())
+    }
+
+    /// Identifies this writer among those made by this process, for telling them apart in
+    /// `ReadOnlyHashMap::set_on_foreign_clear`.  Every `clone_writer` gets an id of its own.
+    pub fn writer_id(&self) -> u64 {
+        self.outbox.id
+    }
+
+    /// Sequence number of the last change this writer sent.  Compare with a reader's
+    /// `applied_version` to see how many writes behind it is.
+    pub fn version(&self) -> u64 {
+        self.outbox.version.load(Ordering::SeqCst)
+    }
+
+    /// Whether any reader was still listening as of the last change sent.  Once the last
+    /// one is gone the writer carries on as a local map: lookups keep working, while
+    /// changes fail with `Disconnected` without being attempted.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// drop(r);
+    /// assert!(w.is_connected());
+    /// assert!(w.insert(2, 2).is_err());
+    /// assert!(!w.is_connected());
+    /// assert!(w.remove(&1).is_err());
+    /// assert_eq!(Some(&1), w.get(&1));
+    /// assert_eq!(1, w.len());
+    /// ```
+    pub fn is_connected(&self) -> bool {
+        !self.outbox.txs.is_empty()
+    }
+
+    /// Blocks until every reader still attached has applied all the changes sent so far.
+    /// Readers only apply changes when used, so this waits for them to next be read from or
+    /// refreshed.  Anything held back by `with_coalescing` is published first.  Fails with
+    /// `Disconnected` if a reader was dropped before applying everything, or if there are
+    /// no readers at all.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::thread;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// let t = thread::spawn(move || {
+    ///     while r.pending_len() > 0 {
+    ///         r.refresh().unwrap();
+    ///     }
+    /// });
+    /// w.flush().unwrap();
+    /// t.join().unwrap();
+    /// ```
+    pub fn flush(&mut self) -> Result<()> {
+        self.flush_until(None)
+    }
+
+    /// Like `flush`, but fails with `Timeout` if the readers haven't all caught up once
+    /// `timeout` has passed
+    ///
+    /// ```
+    /// use ecds::errors::ErrorKind;
+    /// use ecds::keyvalue;
+    /// use std::time::Duration;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert(1, 1).unwrap();
+    /// assert!(w.flush_timeout(Duration::from_millis(10)).unwrap_err().is_timeout());
+    /// r.refresh().unwrap();
+    /// w.flush_timeout(Duration::from_millis(10)).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// let _other = w.clone_writer();
+    /// drop(r);
+    /// assert_eq!(&ErrorKind::Disconnected,
+    ///            w.flush_timeout(Duration::from_millis(10)).unwrap_err().kind());
+    /// ```
+    pub fn flush_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.flush_until(Some(Instant::now() + timeout))
+    }
+
+    fn flush_until(&mut self, deadline: Option<Instant>) -> Result<()> {
+        self.publish()?;
+        if self.outbox.txs.is_empty() {
+            return Err(Disconnected.into());
+        }
+        let mut spins = 0u32;
+        loop {
+            let mut caught_up = true;
+            for tx in &self.outbox.txs {
+                if tx.pending.load(Ordering::SeqCst) > 0 {
+                    if tx.reader_gone() {
+                        return Err(Disconnected.into());
+                    }
+                    caught_up = false;
+                }
+            }
+            if caught_up {
+                return Ok(());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Timeout.into());
+            }
+            if spins < 100 {
+                spins += 1;
+                thread::yield_now();
+            } else {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// Holds changes back instead of sending them straight away, so repeated writes to the
+    /// same key reach readers as a single change.  Held back changes go out together once
+    /// `limit` of them have built up, or on `publish`, `flush` or drop.  Until then,
+    /// failures to reach readers aren't reported.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (w, r) = keyvalue::new();
+    /// let mut w = w.with_coalescing(1000);
+    /// for i in 0..100 {
+    ///     w.insert(1, i).unwrap();
+    /// }
+    /// w.insert(2, 2).unwrap();
+    /// w.remove(&2).unwrap();
+    /// assert_eq!(None, r.get(&1).unwrap());
+    /// w.publish().unwrap();
+    /// assert_eq!(1, r.pending_len());
+    /// assert_eq!(Some(99), r.get(&1).unwrap());
+    /// assert_eq!(None, r.get(&2).unwrap());
+    /// w.insert(3, 3).unwrap();
+    /// drop(w);
+    /// assert_eq!(Some(3), r.get(&3).unwrap());
+    /// ```
+    pub fn with_coalescing(mut self, limit: usize) -> Self {
+        self.outbox.coalescing = Some(Coalescing {
+                                          actions: Vec::new(),
+                                          limit,
+                                          window: None,
+                                          held_since: None,
+                                          deliver: Outbox::deliver,
+                                      });
+        self
+    }
+
+    /// Holds changes back like `with_coalescing`, sending them together once the oldest
+    /// has been held for `window`.  Nothing runs in the background, so this is checked on
+    /// each write: after a pause longer than `window` the changes from before it wait for
+    /// the next write, `publish`, `flush` or drop.  While writes keep coming, readers see
+    /// them at most `window`, plus however long the next write takes to arrive, late.
+    /// Combined with `with_coalescing`, whichever of its limit and `window` is reached
+    /// first sends the changes.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// let (w, r) = keyvalue::new();
+    /// let mut w = w.with_time_batching(Duration::from_millis(20));
+    /// for i in 0..100 {
+    ///     w.insert(1, i).unwrap();
+    /// }
+    /// assert_eq!(None, r.get(&1).unwrap());
+    /// thread::sleep(Duration::from_millis(30));
+    /// w.insert(2, 2).unwrap();
+    /// assert_eq!(Some(99), r.get(&1).unwrap());
+    /// assert_eq!(Some(2), r.get(&2).unwrap());
+    /// ```
+    pub fn with_time_batching(mut self, window: Duration) -> Self {
+        if self.outbox.coalescing.is_none() {
+            self = self.with_coalescing(usize::MAX);
+        }
+        if let Some(ref mut c) = self.outbox.coalescing {
+            c.window = Some(window);
+        }
+        self
+    }
+
+    /// Sends everything held back by `with_coalescing` as one message, carrying the latest
+    /// value of each key touched.  A clear goes out ahead of the values written after it,
+    /// and anything written before it is left out.  Keys go out in the order they were
+    /// last written, which is what readers keeping insertion order expect.  Does nothing
+    /// for a writer that isn't coalescing.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (w, r) = keyvalue::from_iter(vec![(0, 0)]);
+    /// let mut w = w.with_coalescing(1000);
+    /// w.insert(1, 1).unwrap();
+    /// w.clear().unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// w.publish().unwrap();
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// w.insert(3, 3).unwrap();
+    /// w.clear().unwrap();
+    /// w.publish().unwrap();
+    /// assert!(r.is_empty().unwrap());
+    /// w.clear().unwrap();
+    /// w.insert(4, 4).unwrap();
+    /// drop(w);
+    /// assert_eq!(vec![(4, 4)], r.iter().unwrap());
+    ///
+    /// let (w, r) = keyvalue::new();
+    /// let r = r.with_insertion_order();
+    /// let mut w = w.with_coalescing(1000);
+    /// for k in vec!["c", "a", "b", "c"] {
+    ///     w.insert(k, 0).unwrap();
+    /// }
+    /// w.publish().unwrap();
+    /// assert_eq!(vec![("a", 0), ("b", 0), ("c", 0)], r.iter_ordered().unwrap());
+    /// ```
+    pub fn publish(&mut self) -> Result<()> {
+        let actions = match self.outbox.coalescing {
+            Some(ref mut c) if !c.actions.is_empty() => {
+                c.held_since = None;
+                mem::take(&mut c.actions)
+            }
+            _ => return Ok(()),
+        };
+        let mut cleared = false;
+        let mut touched = Vec::new();
+        for action in actions {
+            action.touched_keys(&mut cleared, &mut touched);
+        }
+        // Each key goes out where it was last touched, so readers using
+        // `with_insertion_order` see the order they would have without coalescing
+        let mut seen = HashSet::new();
+        touched.reverse();
+        touched.retain(|k| seen.insert(k.clone()));
+        touched.reverse();
+        let mut batch = Vec::with_capacity(touched.len() + 1);
+        if cleared {
+            batch.push(Action::Clear);
+        }
+        for k in touched {
+            match self.hashmap.get(&k).cloned() {
+                Some(v) => {
+                    if let Some(at) = self.expiries.deadline(&k) {
+                        batch.push(Action::Add(k.clone(), v));
+                        batch.push(Action::Expire(k, at));
+                    } else {
+                        batch.push(Action::Add(k, v));
+                    }
+                }
+                None if !cleared => batch.push(Action::Remove(k)),
+                None => {}
+            }
+        }
+        self.outbox.deliver(Action::Batch(batch), true)
+    }
+
+    fn send(&mut self, action: Action<K, V>, block: bool) -> Result<()> {
+        let action = if self.outbox.collapse_readds {
+            action.collapse_readds()
+        } else {
+            action
+        };
+        // Deadlines only follow actions that actually went out, so keep note of the keys
+        let track = if self.expiries.is_empty() && !action.sets_expiry() {
+            None
+        } else {
+            Some(action.keys_only())
+        };
+        self.outbox.send(action, block)?;
+        if let Some(action) = track {
+            self.expiries.track(&action);
+        }
+        match self.outbox.coalescing {
+            Some(ref mut c) if c.is_due() => {
+                // The local map doesn't reflect this change yet, so publish what came
+                // before it and send this one as it is
+                let action = c.actions.pop();
+                self.publish()?;
+                match action {
+                    Some(action) => self.outbox.deliver(action, block),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<K: Debug, V: Debug, S> Debug for WritableHashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WritableHashMap")
+            .field("hashmap", &self.hashmap)
+            .field("version", &self.outbox.version.load(Ordering::SeqCst))
+            .field("connected", &!self.outbox.txs.is_empty())
+            .finish()
+    }
+}
+
+/// Looks up a key in the writer's local map, panicking if it isn't present
+impl<K, Q, V, S> Index<&Q> for WritableHashMap<K, V, S>
+    where K: Eq + Hash + Borrow<Q>,
+          Q: ?Sized + Eq + Hash,
+          S: BuildHasher
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        &self.hashmap[key]
+    }
+}
+
+/// Hands out writer ids, starting from 1 so that 0 can stand for a writer that isn't known
+fn next_writer_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// An action stamped with the writer's sequence number for it and the id of the writer
+/// that made it, see `WritableHashMap::writer_id`
+type Message<K, V> = (u64, u64, Action<K, V>);
+
+/// The sending half of one reader's channel
+pub(crate) struct Transmitter<K, V> {
+    tx: Tx<K, V>,
+    pending: Arc<AtomicUsize>,
+    /// Only the inbox holds this, so it can't be upgraded once the reader is gone.  Unlike
+    /// `pending`, it isn't shared with other transmitters for the same reader.
+    reader: Weak<()>,
+    /// Shared by every transmitter for the same reader, see `Inbox::writers`
+    writers: Arc<()>,
+    /// See `ReadOnlyHashMap::next_changes`.  Kept last so it is dropped after the channel.
+    #[cfg(feature = "async")]
+    wake: WakeOnDrop,
+}
+
+enum Tx<K, V> {
+    Unbounded(Sender<Message<K, V>>),
+    /// With the channel's capacity, see `Transmitter::reserve`
+    Bounded(SyncSender<Message<K, V>>, usize),
+    /// See `from_transport`
+    Custom(Arc<dyn ChangeSink<K, V> + Send + Sync>),
+}
+
+impl<K, V> Clone for Transmitter<K, V> {
+    fn clone(&self) -> Self {
+        Transmitter {
+            tx: match self.tx {
+                Tx::Unbounded(ref tx) => Tx::Unbounded(tx.clone()),
+                Tx::Bounded(ref tx, capacity) => Tx::Bounded(tx.clone(), capacity),
+                Tx::Custom(ref sink) => Tx::Custom(sink.clone()),
+            },
+            pending: self.pending.clone(),
+            reader: self.reader.clone(),
+            writers: self.writers.clone(),
+            #[cfg(feature = "async")]
+            wake: WakeOnDrop(self.wake.0.clone()),
+        }
+    }
+}
+
+impl<K, V> Transmitter<K, V> {
+    fn custom(sink: Arc<dyn ChangeSink<K, V> + Send + Sync>) -> Self {
+        Transmitter {
+            tx: Tx::Custom(sink),
+            pending: Arc::new(AtomicUsize::new(0)),
+            reader: Weak::new(),
+            writers: Arc::new(()),
+            #[cfg(feature = "async")]
+            wake: WakeOnDrop(Arc::new(WakeSlot::new())),
+        }
+    }
+
+    /// Whether the reader on the other end has been dropped.  Always true for a custom
+    /// sink, which never counts anything as pending.
+    pub(crate) fn reader_gone(&self) -> bool {
+        self.reader.strong_count() == 0
+    }
+
+    /// Sends `message`, only waiting for room in a bounded channel if `block` is set.
+    /// Fails with `Full` if it didn't wait and there was no room, or `Disconnected`.
+    fn send(&self, message: Message<K, V>, block: bool) -> Result<()> {
+        if !self.reserve(block) {
+            return Err(Full.into());
+        }
+        self.send_reserved(message, block)
+    }
+
+    /// Counts a change as pending ahead of sending it, so the reader never sees the
+    /// counter go negative.  Without `block`, a bounded channel only counts it while
+    /// fewer than its capacity are pending, and returns false otherwise.  Everything in
+    /// the channel is still counted, so once this succeeds the change is sure to fit.
+    fn reserve(&self, block: bool) -> bool {
+        match self.tx {
+            Tx::Custom(_) => true,
+            Tx::Bounded(_, capacity) if !block => {
+                self.pending
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                        if n < capacity { Some(n + 1) } else { None }
+                    })
+                    .is_ok()
+            }
+            _ => {
+                self.pending.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        }
+    }
+
+    /// Gives back what `reserve` took, for a change that won't be sent after all
+    fn unreserve(&self) {
+        if let Tx::Custom(_) = self.tx {
+            return;
+        }
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Sends `message` once `reserve` has counted it
+    fn send_reserved(&self, message: Message<K, V>, block: bool) -> Result<()> {
+        if let Tx::Custom(ref sink) = self.tx {
+            return sink.send(message.2);
+        }
+        let result = match self.tx {
+            Tx::Unbounded(ref tx) => {
+                tx.send(message)
+                    .map_err(|SendError(a)| TrySendError::Disconnected(a))
+            }
+            Tx::Bounded(ref tx, _) if block => {
+                tx.send(message)
+                    .map_err(|SendError(a)| TrySendError::Disconnected(a))
+            }
+            Tx::Bounded(ref tx, _) => tx.try_send(message),
+            Tx::Custom(_) => unreachable!("sent above"),
+        };
+        match result {
+            Ok(()) => {
+                #[cfg(feature = "async")]
+                self.wake.0.wake();
+                Ok(())
+            }
+            Err(error) => {
+                self.unreserve();
+                match error {
+                    TrySendError::Full(_) => Err(Full.into()),
+                    TrySendError::Disconnected(_) => Err(Disconnected.into()),
+                }
+            }
+        }
+    }
+}
+
+/// The receiving half of a reader's channel
+pub(crate) struct Inbox<K, V> {
+    rx: Rx<K, V>,
+    pending: Arc<AtomicUsize>,
+    /// Only held, never read, see `Transmitter::reader`
+    _reader: Arc<()>,
+    /// Can be upgraded for as long as any transmitter is left
+    writers: Weak<()>,
+    #[cfg(feature = "async")]
+    wake: Arc<WakeSlot>,
+    closed: Cell<bool>,
+    /// Sequence number of the last change received
+    applied: Cell<u64>,
+    /// Changes taken off the channel but not handed out yet, so they can be looked at
+    /// without being applied.  Everything is received through here, oldest first; see
+    /// `ReadOnlyHashMap::peek_pending`.
+    staged: RefCell<VecDeque<Message<K, V>>>,
+}
+
+enum Rx<K, V> {
+    Channel(Receiver<Message<K, V>>),
+    /// See `from_transport`
+    Custom(Box<dyn ChangeSource<K, V> + Send>),
+}
+
+impl<K, V> Inbox<K, V> {
+    fn custom(source: Box<dyn ChangeSource<K, V> + Send>) -> Self {
+        Inbox {
+            rx: Rx::Custom(source),
+            pending: Arc::new(AtomicUsize::new(0)),
+            _reader: Arc::new(()),
+            writers: Weak::new(),
+            #[cfg(feature = "async")]
+            wake: Arc::new(WakeSlot::new()),
+            closed: Cell::new(false),
+            applied: Cell::new(0),
+            staged: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Takes every change that has arrived so far, in order
+    pub(crate) fn drain(&self) -> Vec<Action<K, V>> {
+        self.drain_numbered()
+            .into_iter()
+            .map(|(_, _, action)| action)
+            .collect()
+    }
+
+    /// Like `drain`, keeping each change's sequence number.  Changes from a custom source
+    /// don't have one, so they all come numbered 0.
+    fn drain_numbered(&self) -> Vec<Message<K, V>> {
+        self.take().collect()
+    }
+
+    /// Takes every change that has arrived so far, counting each as received only once
+    /// it has been handed out, see `Taken`
+    fn take(&self) -> Taken<'_, K, V> {
+        self.stage();
+        let messages: Vec<_> = self.staged.borrow_mut().drain(..).collect();
+        Taken {
+            inbox: self,
+            messages: messages.into_iter(),
+            received: None,
+        }
+    }
+
+    /// Moves every change that has arrived so far onto the back of `staged`, without
+    /// counting any of them as received
+    fn stage(&self) {
+        let mut staged = self.staged.borrow_mut();
+        let rx = match self.rx {
+            Rx::Channel(ref rx) => rx,
+            Rx::Custom(ref source) => {
+                match source.try_drain() {
+                    Ok(actions) => staged.extend(actions.into_iter().map(|a| (0, 0, a))),
+                    Err(_) => self.closed.set(true),
+                }
+                return;
+            }
+        };
+        loop {
+            staged.extend(rx.try_iter());
+            // try_iter stops on disconnection as well as on an empty channel, so look once
+            // more to tell them apart, keeping anything that slipped in meanwhile
+            match rx.try_recv() {
+                Ok(message) => staged.push_back(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed.set(true);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Whether a change is staged or waiting, staging at most one to find out
+    fn has_pending(&self) -> bool {
+        if !self.staged.borrow().is_empty() {
+            return true;
+        }
+        match self.rx {
+            Rx::Channel(ref rx) => {
+                match rx.try_recv() {
+                    Ok(message) => {
+                        self.staged.borrow_mut().push_back(message);
+                        true
+                    }
+                    Err(TryRecvError::Empty) => false,
+                    Err(TryRecvError::Disconnected) => {
+                        self.closed.set(true);
+                        false
+                    }
+                }
+            }
+            Rx::Custom(_) => {
+                self.stage();
+                !self.staged.borrow().is_empty()
+            }
+        }
+    }
+
+    /// Like `wait_until`, but with no deadline
+    fn wait(&self) {
+        match self.rx {
+            Rx::Channel(ref rx) => {
+                match rx.recv() {
+                    Ok(message) => self.staged.borrow_mut().push_back(message),
+                    Err(_) => self.closed.set(true),
+                }
+            }
+            Rx::Custom(_) => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+
+    /// Blocks until a change arrives and stages it, or until `deadline`.  A custom source
+    /// can't be waited on, so this only pauses briefly before it is next drained.
+    fn wait_until(&self, deadline: Instant) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.rx {
+            Rx::Channel(ref rx) => {
+                match rx.recv_timeout(timeout) {
+                    Ok(message) => self.staged.borrow_mut().push_back(message),
+                    Err(RecvTimeoutError::Disconnected) => self.closed.set(true),
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+            }
+            Rx::Custom(_) => thread::sleep(timeout.min(Duration::from_millis(1))),
+        }
+    }
+
+    /// Counts `count` changes, the last numbered `seq`, as received.  A custom source
+    /// never counts what it sends, so there's nothing to do for one.
+    fn received(&self, seq: u64, count: usize) {
+        if let Rx::Channel(_) = self.rx {
+            self.applied.set(seq);
+            self.pending.fetch_sub(count, Ordering::SeqCst);
+        }
+    }
+
+    /// Blocks for the next changes and hands them to `apply`, only counting them as
+    /// applied once that returns.  Returns `false` once the writer is gone.  A custom
+    /// source can't be waited on, so it is polled instead.
+    fn recv_with<F: FnMut(Action<K, V>)>(&self, mut apply: F) -> bool {
+        let staged: Vec<_> = self.staged.borrow_mut().drain(..).collect();
+        if let Some(&(seq, _, _)) = staged.last() {
+            let count = staged.len();
+            staged.into_iter().for_each(|(_, _, action)| apply(action));
+            self.received(seq, count);
+            return true;
+        }
+        let rx = match self.rx {
+            Rx::Channel(ref rx) => rx,
+            Rx::Custom(ref source) => {
+                loop {
+                    match source.try_drain() {
+                        Ok(ref actions) if actions.is_empty() => {
+                            thread::sleep(Duration::from_millis(1))
+                        }
+                        Ok(actions) => {
+                            actions.into_iter().for_each(apply);
+                            return true;
+                        }
+                        Err(_) => {
+                            self.closed.set(true);
+                            return false;
+                        }
+                    }
+                }
+            }
+        };
+        match rx.recv() {
+            Ok((seq, _, action)) => {
+                apply(action);
+                self.received(seq, 1);
+                true
+            }
+            Err(_) => {
+                self.closed.set(true);
+                false
+            }
+        }
+    }
+
+    /// Whether the writer is known to be gone, without draining the channel
+    fn writer_gone(&self) -> bool {
+        match self.rx {
+            Rx::Channel(_) => self.closed.get() || self.writers.strong_count() == 0,
+            Rx::Custom(_) => self.closed.get(),
+        }
+    }
+
+    /// Fails with `WriterGone` once the channel has been seen to disconnect
+    pub(crate) fn check_open(&self) -> Result<()> {
+        if self.closed.get() {
+            Err(WriterGone.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Changes taken from an `Inbox` to be applied.  Any not handed out by the time this is
+/// dropped, say because applying an earlier one panicked, go back on the inbox to be
+/// applied next time instead of being lost.
+struct Taken<'a, K: 'a, V: 'a> {
+    inbox: &'a Inbox<K, V>,
+    messages: vec::IntoIter<Message<K, V>>,
+    /// Sequence number of the last change handed out, and how many have been
+    received: Option<(u64, usize)>,
+}
+
+impl<'a, K, V> Iterator for Taken<'a, K, V> {
+    type Item = Message<K, V>;
+
+    fn next(&mut self) -> Option<Message<K, V>> {
+        let message = self.messages.next()?;
+        let count = self.received.map_or(0, |(_, count)| count);
+        self.received = Some((message.0, count + 1));
+        Some(message)
+    }
+}
+
+impl<'a, K, V> AsRef<[Message<K, V>]> for Taken<'a, K, V> {
+    fn as_ref(&self) -> &[Message<K, V>] {
+        self.messages.as_slice()
+    }
+}
+
+impl<'a, K, V> Drop for Taken<'a, K, V> {
+    fn drop(&mut self) {
+        if let Some((seq, count)) = self.received {
+            self.inbox.received(seq, count);
+        }
+        let mut staged = self.inbox.staged.borrow_mut();
+        while let Some(message) = self.messages.next_back() {
+            staged.push_front(message);
+        }
+    }
+}
+
+/// Everything a writer needs to publish changes to its readers.  Clones share the
+/// channels and the sequence counter, but not anything held back for coalescing.
+pub(crate) struct Outbox<K, V> {
+    txs: Vec<Transmitter<K, V>>,
+    /// See `WritableHashMap::writer_id`
+    id: u64,
+    /// Sequence number of the last change sent
+    version: Arc<AtomicU64>,
+    coalescing: Option<Coalescing<K, V>>,
+    /// Log every change is written to before it goes out, see `WritableHashMap::with_wal`
+    wal: Option<Arc<Mutex<Wal<K, V>>>>,
+    /// See `WritableHashMap::with_capacity_hints`
+    capacity_hints: bool,
+    /// See `WritableHashMap::with_readd_collapsing`
+    collapse_readds: bool,
+}
+
+/// Changes held back by a coalescing writer, see `WritableHashMap::with_coalescing`
+struct Coalescing<K, V> {
+    actions: Vec<Action<K, V>>,
+    limit: usize,
+    /// See `WritableHashMap::with_time_batching`
+    window: Option<Duration>,
+    /// When the oldest change still held back was made, if there is a window
+    held_since: Option<Instant>,
+    /// `Outbox::deliver`, kept here so dropping the outbox can ship what's left without
+    /// needing `Clone` bounds
+    deliver: Deliver<K, V>,
+}
+
+type Deliver<K, V> = fn(&mut Outbox<K, V>, Action<K, V>, bool) -> Result<()>;
+
+impl<K, V> Coalescing<K, V> {
+    /// Whether enough has been held back, or for long enough, that it should go out
+    fn is_due(&self) -> bool {
+        self.actions.len() >= self.limit ||
+        match (self.window, self.held_since) {
+            (Some(window), Some(since)) => since.elapsed() >= window,
+            _ => false,
+        }
+    }
+}
+
+impl<K, V> Clone for Outbox<K, V> {
+    fn clone(&self) -> Self {
+        Outbox {
+            txs: self.txs.clone(),
+            id: next_writer_id(),
+            version: self.version.clone(),
+            coalescing: self.coalescing
+                .as_ref()
+                .map(|c| {
+                         Coalescing {
+                             actions: Vec::new(),
+                             limit: c.limit,
+                             window: c.window,
+                             held_since: None,
+                             deliver: c.deliver,
+                         }
+                     }),
+            wal: self.wal.clone(),
+            capacity_hints: self.capacity_hints,
+            collapse_readds: self.collapse_readds,
+        }
+    }
+}
+
+impl<K, V> Drop for Outbox<K, V> {
+    fn drop(&mut self) {
+        if let Some(c) = self.coalescing.take() {
+            if !c.actions.is_empty() {
+                let _ = (c.deliver)(self, Action::Batch(c.actions), true);
+            }
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Outbox<K, V> {
+    pub(crate) fn new(txs: Vec<Transmitter<K, V>>) -> Self {
+        Outbox {
+            txs,
+            id: next_writer_id(),
+            version: Arc::new(AtomicU64::new(0)),
+            coalescing: None,
+            wal: None,
+            capacity_hints: false,
+            collapse_readds: false,
+        }
+    }
+
+    /// Delivers an action, or holds it back if this outbox is coalescing.  Once every
+    /// reader is gone this fails straight away, without holding anything back.
+    pub(crate) fn send(&mut self, action: Action<K, V>, block: bool) -> Result<()> {
+        if self.txs.is_empty() {
+            return Err(Disconnected.into());
+        }
+        match self.coalescing {
+            Some(ref mut c) => {
+                // A clear is a barrier: nothing held back from before it can still matter
+                if matches!(action, Action::Clear | Action::ClearKeepCapacity(_) | Action::Replace(_)) {
+                    c.actions.clear();
+                }
+                if c.window.is_some() && c.held_since.is_none() {
+                    c.held_since = Some(Instant::now());
+                }
+                c.actions.push(action);
+                Ok(())
+            }
+            None => self.deliver(action, block),
+        }
+    }
+
+    /// Delivers an action to every reader still listening, forgetting the ones that have
+    /// gone away.  Only fails once there is nobody left to receive it, or when `block` is
+    /// unset and a bounded channel had no room, in which case no reader is sent it.
+    fn deliver(&mut self, action: Action<K, V>, block: bool) -> Result<()> {
+        // A reader that's gone never takes anything off its channel, so if it was left full
+        // it would turn every change away with `Full` from then on
+        self.txs.retain(|tx| matches!(tx.tx, Tx::Custom(_)) || !tx.reader_gone());
+        // Keep the log from recording changes that never went out
+        if self.txs.is_empty() {
+            return Err(Disconnected.into());
+        }
+        // Make room on every channel before sending down any of them, so a change that
+        // doesn't fit in a bounded one doesn't reach the other readers either
+        let reserved = self.txs.iter().take_while(|tx| tx.reserve(block)).count();
+        if reserved < self.txs.len() {
+            self.txs[..reserved].iter().for_each(Transmitter::unreserve);
+            return Err(Full.into());
+        }
+        // The log stays locked until the change is out, so it records changes in the
+        // order they were numbered and can take back one that nobody received
+        let mut wal = self.wal
+            .as_ref()
+            .map(|wal| wal.lock().unwrap_or_else(PoisonError::into_inner));
+        let logged = match wal {
+            Some(ref mut wal) => {
+                match wal.append(&action) {
+                    Ok(start) => Some(start),
+                    Err(e) => {
+                        self.txs.iter().for_each(Transmitter::unreserve);
+                        return Err(e);
+                    }
+                }
+            }
+            None => None,
+        };
+        let seq = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+        let id = self.id;
+        let n = self.txs.len();
+        let mut action = Some(action);
+        let mut i = 0;
+        let mut delivered = false;
+        let mut full = false;
+        self.txs
+            .retain(|tx| {
+                        i += 1;
+                        let a = if i == n {
+                            action.take()
+                        } else {
+                            action.clone()
+                        };
+                        match a.map(|a| tx.send_reserved((seq, id, a), block)) {
+                            Some(Ok(())) => {
+                                delivered = true;
+                                true
+                            }
+                            Some(Err(Error(Full, _))) => {
+                                full = true;
+                                true
+                            }
+                            _ => false,
+                        }
+                    });
+        if !delivered {
+            // Nobody saw this sequence number, so hand it back to keep versions gapless
+            let _ = self.version
+                .compare_exchange(seq, seq - 1, Ordering::SeqCst, Ordering::SeqCst);
+            // and take the change back out of the log so a restart doesn't replay it
+            if let (Some(wal), Some(start)) = (wal.as_mut(), logged) {
+                wal.truncate(start)?;
+            }
+        }
+        if self.txs.is_empty() {
+            Err(Disconnected.into())
+        } else if full {
+            Err(Full.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Changes staged by `WritableHashMap::transaction`
+pub struct Transaction<K, V> {
+    actions: Vec<Action<K, V>>,
+}
+
+impl<K, V> Transaction<K, V> {
+    pub fn insert(&mut self, k: K, v: V) {
+        self.actions.push(Action::Add(k, v));
+    }
+
+    pub fn remove(&mut self, k: K) {
+        self.actions.push(Action::Remove(k));
+    }
+
+    pub fn clear(&mut self) {
+        self.actions.push(Action::Clear);
+    }
+}
+
+/// Mutable access to a value of a `WritableHashMap`, see `WritableHashMap::get_mut`
+pub struct GuardedMut<'a, K, V, S = RandomState>
+    where K: 'a + Eq + Hash + Clone,
+          V: 'a + Clone,
+          S: 'a + BuildHasher
+{
+    key: K,
+    writer: &'a mut WritableHashMap<K, V, S>,
+    /// Set once the value has been borrowed mutably and not yet published
+    changed: bool,
+}
+
+impl<'a, K, V, S> GuardedMut<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Publishes the current value now rather than on drop, reporting whether it could be
+    /// delivered
+    pub fn commit(mut self) -> Result<()> {
+        self.publish()
+    }
+
+    fn publish(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.changed = false;
+        let value = self.writer.hashmap[&self.key].clone();
+        self.writer.send(Action::Add(self.key.clone(), value), true)
+    }
+}
+
+impl<'a, K, V, S> Deref for GuardedMut<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.writer.hashmap[&self.key]
+    }
+}
+
+impl<'a, K, V, S> DerefMut for GuardedMut<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    fn deref_mut(&mut self) -> &mut V {
+        self.changed = true;
+        // The guard borrows the writer for as long as it lives, so nothing can remove the key
+        self.writer.hashmap.get_mut(&self.key).expect("guarded key is present")
+    }
+}
+
+impl<'a, K, V, S> Drop for GuardedMut<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    fn drop(&mut self) {
+        let _ = self.publish();
+    }
+}
+
+/// A view into a single key of a `WritableHashMap`.  Values are only handed out by shared
+/// reference so every mutation has to go through the entry, where it can be published to
+/// the readers.
+///
+/// ```
+/// use ecds::keyvalue;
+/// let (mut w, r) = keyvalue::new();
+/// for _ in 0..3 {
+///     w.entry("hits").and_modify(|v| *v += 1).unwrap().or_insert(1).unwrap();
+/// }
+/// assert_eq!(Some(3), r.get("hits").unwrap());
+/// ```
+pub struct Entry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    writer: &'a mut WritableHashMap<K, V, S>,
+    key: K,
+    /// Whether this entry has sent anything to the readers yet
+    changed: bool,
+}
+
+/// What an `Entry` did once it is done with, see `Entry::commit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Committed {
+    /// Whether a change was sent to the readers
+    pub changed: bool,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn or_insert(self, default: V) -> Result<&'a V> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> Result<&'a V> {
+        let writer = self.writer;
+        if writer.hashmap.contains_key(&self.key) {
+            return Ok(&writer.hashmap[&self.key]);
+        }
+        let v = f();
+        writer.send(Action::Add(self.key.clone(), v.clone()), true)?;
+        Ok(writer.hashmap.entry(self.key).or_insert(v))
+    }
+
+    /// Runs `f` against a copy of the current value, if there is one.  The result is
+    /// published to the readers before it replaces the local value.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Result<Self> {
+        self.modify(f, |_, _| true)
+    }
+
+    /// Like `and_modify`, but sends nothing when `f` leaves the value as it was
+    pub fn and_modify_if_changed<F: FnOnce(&mut V)>(self, f: F) -> Result<Self>
+        where V: PartialEq
+    {
+        self.modify(f, |old, new| old != new)
+    }
+
+    fn modify<F, C>(self, f: F, changed: C) -> Result<Self>
+        where F: FnOnce(&mut V),
+              C: FnOnce(&V, &V) -> bool
+    {
+        let v = match self.writer.hashmap.get(&self.key) {
+            Some(old) => {
+                let mut v = old.clone();
+                f(&mut v);
+                if !changed(old, &v) {
+                    return Ok(self);
+                }
+                v
+            }
+            None => return Ok(self),
+        };
+        self.writer.send(Action::Add(self.key.clone(), v.clone()), true)?;
+        self.writer.hashmap.insert(self.key.clone(), v);
+        Ok(Entry {
+               changed: true,
+               ..self
+           })
+    }
+
+    /// Like `or_insert`, but reports whether anything was sent to the readers, counting
+    /// earlier calls on this entry, instead of handing back the value.  Inserting over a
+    /// key that is already present sends nothing.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let mut changes = 0;
+    /// for v in vec![1, 1, 2] {
+    ///     let committed = w.entry("a")
+    ///         .and_modify_if_changed(|old| *old = v)
+    ///         .unwrap()
+    ///         .or_insert_and_commit(v)
+    ///         .unwrap();
+    ///     if committed.changed {
+    ///         changes += 1;
+    ///     }
+    /// }
+    /// assert_eq!(2, changes);
+    /// assert_eq!(2, w.version());
+    /// assert_eq!(Some(2), r.get("a").unwrap());
+    /// ```
+    pub fn or_insert_and_commit(self, default: V) -> Result<Committed> {
+        let changed = self.changed || !self.writer.hashmap.contains_key(&self.key);
+        self.or_insert(default)?;
+        Ok(Committed { changed })
+    }
+
+    /// Finishes with this entry, reporting whether it sent anything to the readers
+    pub fn commit(self) -> Committed {
+        Committed { changed: self.changed }
+    }
+}
+
+/// Like `Entry`, but for a borrowed key, so the owned key is only built if an insert
+/// actually happens, see `WritableHashMap::entry_ref`
+pub struct EntryRef<'a, 'q, K: 'a, V: 'a, Q: 'q + ?Sized, S: 'a = RandomState> {
+    writer: &'a mut WritableHashMap<K, V, S>,
+    key: &'q Q,
+}
+
+impl<'a, 'q, K, V, Q, S> EntryRef<'a, 'q, K, V, Q, S>
+    where K: Eq + Hash + Clone + Borrow<Q> + From<&'q Q>,
+          V: Clone,
+          Q: ?Sized + Hash + Eq,
+          S: BuildHasher
+{
+    pub fn key(&self) -> &Q {
+        self.key
+    }
+
+    pub fn or_insert(self, default: V) -> Result<&'a V> {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> Result<&'a V> {
+        let writer = self.writer;
+        if writer.hashmap.contains_key(self.key) {
+            return Ok(&writer.hashmap[self.key]);
+        }
+        let k = K::from(self.key);
+        let v = f();
+        writer.send(Action::Add(k.clone(), v.clone()), true)?;
+        Ok(writer.hashmap.entry(k).or_insert(v))
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use errors::Result;
+
+use super::wal::{read_frame, write_frame};
+use super::{open, Action, Change, Codec, ReadOnlyHashMap, WritableHashMap};
+
+
+/// Number of frames a server keeps for clients resuming after a dropped connection
+const HISTORY: usize = 1024;
+
+/// How often the thread accepting clients checks whether the writer is gone
+const POLL: Duration = Duration::from_millis(10);
+
+/// Longest a client waits between attempts to reconnect
+const MAX_RETRY: Duration = Duration::from_secs(1);
+
+/// Bytes queued for a client's own thread to write out, see `spawn_client`
+type Outgoing = SyncSender<Arc<Vec<u8>>>;
+
+/// State shared between the thread following the writer and the one accepting clients
+struct Server<K, V, C> {
+    mirror: HashMap<K, V>,
+    clients: Vec<Outgoing>,
+    codec: C,
+    /// Cleared once the writer is gone, after the clients have been told
+    open: bool,
+    /// Tells this `serve` apart from any other a client might have been following, so it
+    /// never resumes from someone else's sequence numbers
+    epoch: u64,
+    /// Sequence number of the last frame sent
+    seq: u64,
+    /// The last `HISTORY` frames sent
+    history: VecDeque<Frame<K, V>>,
+}
+
+/// The changes sent together, with their sequence number
+type Frame<K, V> = (u64, Vec<Change<K, V>>);
+
+impl<K: Clone, V: Clone, C: Codec<K, V>> Server<K, V, C> {
+    /// Queues what brings a new client up to date.  One that was following this server
+    /// from `seq` is sent just the frames it missed, if they are all still kept, and any
+    /// other the whole map.  Nothing is written to the network while the server is locked.
+    fn catch_up(&self, client: &Outgoing, (epoch, seq): (u64, u64)) -> io::Result<()> {
+        queue(client, Arc::new(self.epoch.to_le_bytes().to_vec()))?;
+        let resumable = epoch == self.epoch && seq <= self.seq &&
+                        self.history.front().map_or(seq == self.seq, |&(first, _)| first <= seq + 1);
+        if resumable {
+            for &(s, ref changes) in self.history.iter().filter(|&&(s, _)| s > seq) {
+                queue(client, encode_frame(&self.codec, s, changes)?)?;
+            }
+            return Ok(());
+        }
+        let snapshot: Vec<_> = Some(Change::Cleared)
+            .into_iter()
+            .chain(self.mirror.iter().map(|(k, v)| {
+                                              Change::Added {
+                                                  key: k.clone(),
+                                                  value: v.clone(),
+                                              }
+                                          }))
+            .collect();
+        queue(client, encode_frame(&self.codec, self.seq, &snapshot)?)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Encodes a frame of `changes` preceded by its sequence number, to be written in one go
+fn encode_frame<K, V, C>(codec: &C, seq: u64, changes: &[Change<K, V>]) -> io::Result<Arc<Vec<u8>>>
+    where C: Codec<K, V>
+{
+    let mut bytes = seq.to_le_bytes().to_vec();
+    write_frame(codec, changes, &mut bytes)?;
+    Ok(Arc::new(bytes))
+}
+
+/// Hands `bytes` to a client's thread without waiting, failing if it has fallen so far
+/// behind that its queue is full, or has gone
+fn queue(client: &Outgoing, bytes: Arc<Vec<u8>>) -> io::Result<()> {
+    client.try_send(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "client fell behind"))
+}
+
+/// Starts a thread that writes out whatever is queued for `stream`, so a slow client only
+/// ever holds itself up.  The thread exits, closing the connection, once the client is
+/// dropped from the server and everything queued has been written, or on the first
+/// failed write.
+fn spawn_client(mut stream: TcpStream) -> Outgoing {
+    // Enough for a full catch-up from the history with room to spare for what follows
+    let (tx, rx) = sync_channel::<Arc<Vec<u8>>>(2 * HISTORY);
+    thread::spawn(move || for bytes in rx {
+                      if stream.write_all(&bytes).is_err() {
+                          return;
+                      }
+                  });
+    tx
+}
+
+/// Reads a frame written by `send_frame`, or `None` at a clean end of stream
+fn recv_frame<K, V, C, R>(codec: &C, r: &mut R) -> io::Result<Option<Frame<K, V>>>
+    where C: Codec<K, V>,
+          R: Read
+{
+    let seq = read_u64(r)?;
+    Ok(read_frame(codec, r)?.map(|changes| (seq, changes)))
+}
+
+/// Replicates `writer` to every reader that connects to `listener` through `connect`.
+/// Each client is first sent the whole map, then every change made after that.  A
+/// client reconnecting after a brief drop is only sent the changes it missed, as long as
+/// this is still the server it was following and they're among the last 1024 it sent.
+///
+/// The changes reach the network from a background thread that keeps its own copy of the
+/// map, so the writer never waits on a slow client.  Each client is written to from a
+/// thread of its own, and one that falls too far behind is disconnected rather than
+/// holding up the others; it catches up when it reconnects.  The background threads stop,
+/// and the listener is closed, once the writer is dropped.
+///
+/// ```
+/// use ecds::keyvalue::{self, net, Change, Codec};
+/// use std::io;
+/// use std::net::TcpListener;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// struct Bytes;
+/// impl Codec<u8, u8> for Bytes {
+///     fn encode(&self, change: &Change<u8, u8>, out: &mut Vec<u8>) -> io::Result<()> {
+///         match *change {
+///             Change::Added { key, value } => out.extend_from_slice(&[0, key, value]),
+///             Change::Removed { key } => out.extend_from_slice(&[1, key]),
+///             Change::Cleared => out.push(2),
+///         }
+///         Ok(())
+///     }
+///     fn decode(&self, bytes: &[u8]) -> io::Result<Change<u8, u8>> {
+///         match bytes {
+///             [0, key, value] => Ok(Change::Added { key: *key, value: *value }),
+///             [1, key] => Ok(Change::Removed { key: *key }),
+///             [2] => Ok(Change::Cleared),
+///             _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad change")),
+///         }
+///     }
+/// }
+///
+/// let (mut w, _r) = keyvalue::from_iter(vec![(1, 1)]);
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// net::serve(&mut w, listener, Bytes);
+///
+/// let remote = net::connect(addr, Bytes).unwrap();
+/// w.insert(2, 2).unwrap();
+/// while remote.get(&2).unwrap().is_none() {
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// assert_eq!(Some(1), remote.get(&1).unwrap());
+/// drop(w);
+/// while remote.refresh().is_ok() {
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// ```
+pub fn serve<K, V, S, C>(writer: &mut WritableHashMap<K, V, S>, listener: TcpListener, codec: C)
+    where K: Eq + Hash + Clone + Send + 'static,
+          V: Clone + Send + 'static,
+          S: BuildHasher,
+          C: Codec<K, V> + Send + 'static
+{
+    let (tx, inbox) = open(None);
+    let server = Arc::new(Mutex::new(Server {
+                                         mirror: writer.hashmap
+                                             .iter()
+                                             .map(|(k, v)| (k.clone(), v.clone()))
+                                             .collect(),
+                                         clients: Vec::new(),
+                                         codec,
+                                         open: true,
+                                         epoch: SystemTime::now()
+                                             .duration_since(UNIX_EPOCH)
+                                             .map_or(1, |d| d.as_nanos() as u64)
+                                             .max(1),
+                                         seq: 0,
+                                         history: VecDeque::new(),
+                                     }));
+    writer.outbox.txs.push(tx);
+
+    let accepting = server.clone();
+    // Poll for clients rather than block in accept, so the listener can be closed once
+    // the writer is gone without waiting for another client to connect
+    let polling = listener.set_nonblocking(true).is_ok();
+    thread::spawn(move || loop {
+                      let mut stream = match listener.accept() {
+                          Ok((stream, _)) => stream,
+                          Err(_) => {
+                              if !lock(&accepting).open {
+                                  return;
+                              }
+                              if polling {
+                                  thread::sleep(POLL);
+                              }
+                              continue;
+                          }
+                      };
+                      let _ = stream.set_nonblocking(false);
+                      let _ = stream.set_nodelay(true);
+                      // Wait for the client to say where it is before locking, so a slow
+                      // one can't hold up the writer's changes
+                      let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                      let resume = match read_u64(&mut stream)
+                                .and_then(|epoch| read_u64(&mut stream).map(|seq| (epoch, seq))) {
+                          Ok(resume) => resume,
+                          Err(_) => continue,
+                      };
+                      let _ = stream.set_read_timeout(None);
+                      let client = spawn_client(stream);
+                      let mut server = lock(&accepting);
+                      if !server.open {
+                          return;
+                      }
+                      if server.catch_up(&client, resume).is_ok() {
+                          server.clients.push(client);
+                      }
+                  });
+
+    thread::spawn(move || {
+        let apply = |action: Action<K, V>| {
+            let mut server = lock(&server);
+            let Server {
+                ref mut mirror,
+                ref mut clients,
+                ref codec,
+                ref mut seq,
+                ref mut history,
+                ..
+            } = *server;
+            let mut changes = Vec::new();
+            action.apply_to(mirror, &mut |change| changes.extend(change.to_change()));
+            // An empty frame means the writer is gone, so never send one for a no-op
+            if !changes.is_empty() {
+                *seq += 1;
+                match encode_frame(codec, *seq, &changes) {
+                    Ok(bytes) => clients.retain(|client| queue(client, bytes.clone()).is_ok()),
+                    // There's no telling what a client would make of what follows
+                    // without this frame, so have them all start over
+                    Err(_) => clients.clear(),
+                }
+                history.push_back((*seq, changes));
+                if history.len() > HISTORY {
+                    history.pop_front();
+                }
+            }
+        };
+        while inbox.recv_with(apply) {}
+        let mut server = lock(&server);
+        let Server {
+            ref mut clients,
+            ref codec,
+            ..
+        } = *server;
+        let last = encode_frame(codec, 0, &[]);
+        for client in clients.drain(..) {
+            if let Ok(ref bytes) = last {
+                let _ = queue(&client, bytes.clone());
+            }
+        }
+        server.open = false;
+    });
+}
+
+/// Builds a reader fed from a writer shared with `serve` at `addr`.  If the connection
+/// drops, it keeps reconnecting in the background and picks up where it left off, or
+/// from a fresh copy of the map if the server can't replay what it missed.  In the
+/// meantime lookups see the state from before the drop.  Once the writer is gone the
+/// reader behaves as if its writer had been dropped.
+pub fn connect<K, V, A, C>(addr: A, codec: C) -> Result<ReadOnlyHashMap<K, V>>
+    where K: Eq + Hash + Send + 'static,
+          V: Send + 'static,
+          A: ToSocketAddrs,
+          C: Codec<K, V> + Send + 'static
+{
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let mut stream = Some(TcpStream::connect(&addrs[..])?);
+    let (tx, inbox) = open(None);
+    thread::spawn(move || {
+        let mut seq = 0;
+        // The server and sequence number of the last frame received, with no server at
+        // first so the whole map is sent
+        let mut resume: (u64, u64) = (0, 0);
+        // Grows with each attempt that gets nothing through, so a server that keeps
+        // turning the client away isn't hammered
+        let mut retry = Duration::from_millis(10);
+        loop {
+            let stream = match stream.take() {
+                Some(stream) => stream,
+                None => {
+                    thread::sleep(retry);
+                    retry = (retry * 2).min(MAX_RETRY);
+                    match TcpStream::connect(&addrs[..]) {
+                        Ok(stream) => stream,
+                        Err(_) if tx.reader_gone() => return,
+                        Err(_) => continue,
+                    }
+                }
+            };
+            let mut handshake = resume.0.to_le_bytes().to_vec();
+            handshake.extend_from_slice(&resume.1.to_le_bytes());
+            let mut stream = BufReader::new(stream);
+            let epoch = match stream.get_mut()
+                      .write_all(&handshake)
+                      .and_then(|_| read_u64(&mut stream)) {
+                Ok(epoch) => epoch,
+                Err(_) => continue,
+            };
+            while let Ok(Some((frame, changes))) = recv_frame(&codec, &mut stream) {
+                if changes.is_empty() {
+                    return;
+                }
+                seq += 1;
+                let batch = Action::Batch(changes.into_iter().map(Action::from).collect());
+                if tx.send((seq, 0, batch), true).is_err() {
+                    return;
+                }
+                resume = (epoch, frame);
+                retry = Duration::from_millis(10);
+            }
+        }
+    });
+    Ok(ReadOnlyHashMap::from_map(HashMap::new(), inbox))
+}
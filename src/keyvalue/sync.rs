@@ -0,0 +1,436 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher,Hash};
+use std::sync::{Arc,Mutex,MutexGuard,PoisonError,RwLock,RwLockReadGuard,TryLockError};
+use std::thread;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use arc_swap::ArcSwap;
+
+use errors::Result;
+
+use super::bloom::Bloom;
+use super::{Action, ChangeRef, Inbox, ReadOnlyHashMap, Rx};
+
+
+/// A reader that can be shared between threads, for example behind an `Arc`.  Lookups
+/// take a read lock on the map, while draining the channel is serialized behind a mutex
+/// so each change is only ever applied once.  A lookup that finds another thread already
+/// draining doesn't wait for it, and reads the map as it stands, so it may not see the
+/// changes that thread is still applying.  `refresh` does wait.
+///
+/// ```
+/// use ecds::keyvalue;
+/// use std::sync::Arc;
+/// use std::thread;
+/// let (mut w, r) = keyvalue::new();
+/// let r = Arc::new(r.into_sync());
+/// w.insert(1, 2).unwrap();
+/// r.refresh().unwrap();
+/// let handles: Vec<_> = (0..4)
+///     .map(|_| {
+///         let r = r.clone();
+///         thread::spawn(move || r.get(&1).unwrap())
+///     })
+///     .collect();
+/// for h in handles {
+///     assert_eq!(Some(2), h.join().unwrap());
+/// }
+/// ```
+pub struct ReadOnlyHashMapSync<K, V, S = RandomState> {
+    hashmap: RwLock<HashMap<K, V, S>>,
+    inbox: Mutex<Inbox<K, V>>,
+    /// The inbox's count of changes not applied yet, readable without its lock.  Only a
+    /// channel keeps count, so there is none for a custom source.
+    pending: Option<Arc<AtomicUsize>>,
+    bloom: Option<Bloom>,
+}
+
+/// A clonable handle onto a thread-safe reader, see `ReadOnlyHashMap::into_shared`
+pub type SharedReadOnly<K, V, S = RandomState> = Arc<ReadOnlyHashMapSync<K, V, S>>;
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Converts this reader into one that can be shared between threads.  Settings made on
+    /// this reader, such as `set_on_change`, `with_capacity_limit`,
+    /// `with_min_refresh_interval` or times to live, don't carry over.
+    pub fn into_sync(self) -> ReadOnlyHashMapSync<K, V, S> {
+        let pending = match self.inbox.rx {
+            Rx::Channel(_) => Some(self.inbox.pending.clone()),
+            Rx::Custom(_) => None,
+        };
+        ReadOnlyHashMapSync {
+            hashmap: RwLock::new(self.hashmap.into_inner()),
+            inbox: Mutex::new(self.inbox),
+            pending,
+            bloom: None,
+        }
+    }
+
+    /// Converts this reader into a handle that can be cloned and shared, for example between
+    /// async tasks.  Whichever clone is used next applies pending changes, while the others
+    /// keep reading the map rather than wait for it, see `ReadOnlyHashMapSync`.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.into_shared();
+    /// let r2 = r.clone();
+    /// w.insert(1, 2).unwrap();
+    /// assert_eq!(Some(2), r2.get(&1).unwrap());
+    /// assert_eq!(Some(2), r.get(&1).unwrap());
+    /// ```
+    pub fn into_shared(self) -> SharedReadOnly<K, V, S> {
+        Arc::new(self.into_sync())
+    }
+
+    /// Moves this reader onto a thread of its own that applies changes as soon as they
+    /// arrive, so lookups through the returned handle never have to drain the channel.
+    /// The thread exits once the writer is dropped.  A callback set with `set_on_change`
+    /// keeps running on that thread, but other settings don't carry over, see
+    /// `into_sync`.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.spawn_applier();
+    /// w.insert(1, 2).unwrap();
+    /// w.flush().unwrap();
+    /// assert_eq!(Some(2), r.clone().get(&1));
+    /// ```
+    pub fn spawn_applier(self) -> SharedReader<K, V, S>
+        where K: Send + Sync + 'static,
+              V: Send + Sync + 'static,
+              S: Send + Sync + 'static
+    {
+        let ReadOnlyHashMap { hashmap, inbox, hooks, .. } = self;
+        let hashmap = Arc::new(RwLock::new(hashmap.into_inner()));
+        let mut observer = hooks.into_inner().observer;
+        let shared = hashmap.clone();
+        thread::spawn(move || {
+            let mut apply = |action: Action<K, V>| {
+                let mut hashmap = shared.write().unwrap_or_else(PoisonError::into_inner);
+                match observer {
+                    Some(ref mut observer) => action.apply_to(&mut hashmap, observer),
+                    None => action.apply_to(&mut hashmap, &mut |_| ()),
+                }
+            };
+            while inbox.recv_with(&mut apply) {}
+        });
+        SharedReader { hashmap }
+    }
+
+    /// Like `spawn_applier`, but the thread publishes a fresh immutable copy of the map
+    /// through an `ArcSwap` after each batch of changes, so lookups are a lock-free load
+    /// and never wait on changes being applied.  Settings made on this reader don't carry
+    /// over, see `into_sync`.
+    ///
+    /// The price is paid on the write side: every batch clones the whole map, however
+    /// few keys it touched, and the old copy lives on until the last lookup holding it is
+    /// done.  A single insert into a map of a million entries copies a million entries,
+    /// so this only pays off when writes are rare compared to reads, or come in large
+    /// batches.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.into_arcswap();
+    /// let before = r.load();
+    /// w.insert(1, 2).unwrap();
+    /// w.flush().unwrap();
+    /// while r.get(&1).is_none() {}
+    /// assert!(before.is_empty());
+    /// ```
+    pub fn into_arcswap(self) -> SnapshotReader<K, V, S>
+        where K: Clone + Send + Sync + 'static,
+              V: Clone + Send + Sync + 'static,
+              S: Clone + Send + Sync + 'static
+    {
+        let ReadOnlyHashMap { hashmap, inbox, .. } = self;
+        let mut working = hashmap.into_inner();
+        let current = Arc::new(ArcSwap::from_pointee(working.clone()));
+        let published = current.clone();
+        thread::spawn(move || {
+            while inbox.recv_with(|action| action.apply_to(&mut working, &mut |_| ())) {
+                for action in inbox.drain() {
+                    action.apply_to(&mut working, &mut |_| ());
+                }
+                published.store(Arc::new(working.clone()));
+            }
+        });
+        SnapshotReader { current }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMapSync<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Keeps a bloom filter of `bits` bits over the keys, so `contains_key` can answer for
+    /// most absent keys without taking the lock on the map.  While no changes are waiting
+    /// to be applied, such a miss takes no lock at all, not even to drain the channel.
+    /// Removing a key can't take it out of the filter, so keys that come and go make it
+    /// less useful until the next clear resets it.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// w.insert("a", 1).unwrap();
+    /// let r = r.into_sync().with_bloom(1024);
+    /// w.insert("b", 2).unwrap();
+    /// w.remove("a").unwrap();
+    /// assert!(r.contains_key("b").unwrap());
+    /// assert!(!r.contains_key("a").unwrap());
+    /// assert!(!r.contains_key("c").unwrap());
+    /// w.clear().unwrap();
+    /// assert!(!r.contains_key("b").unwrap());
+    /// ```
+    pub fn with_bloom(mut self, bits: usize) -> Self {
+        let bloom = Bloom::new(bits);
+        for k in self.hashmap.get_mut().unwrap_or_else(PoisonError::into_inner).keys() {
+            bloom.insert(k);
+        }
+        self.bloom = Some(bloom);
+        self
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        // With nothing waiting, no change could add the key, so a definite miss can be
+        // answered before touching the inbox
+        let idle = self.pending
+            .as_ref()
+            .is_some_and(|pending| pending.load(Ordering::SeqCst) == 0);
+        if let Some(ref bloom) = self.bloom {
+            if idle && !bloom.may_contain(k) {
+                return Ok(false);
+            }
+        }
+        self.process_changes()?;
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.may_contain(k) {
+                return Ok(false);
+            }
+        }
+        Ok(self.read().contains_key(k))
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.process_changes()?;
+        Ok(self.read().len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.process_changes()?;
+        Ok(self.read().is_empty())
+    }
+
+    /// See `ReadOnlyHashMap::refresh`
+    pub fn refresh(&self) -> Result<()> {
+        let inbox = self.lock_inbox();
+        self.apply(&inbox)?;
+        inbox.check_open()
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.lock_inbox().pending.load(Ordering::SeqCst)
+    }
+
+    pub fn applied_version(&self) -> u64 {
+        self.lock_inbox().applied.get()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<K, V, S>> {
+        self.hashmap.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_inbox(&self) -> MutexGuard<'_, Inbox<K, V>> {
+        self.inbox.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn process_changes(&self) -> Result<()> {
+        // Whoever holds the lock applies everything that has arrived, so rather than queue
+        // up behind them, look up in the map as it stands
+        match self.inbox.try_lock() {
+            Ok(inbox) => self.apply(&inbox),
+            Err(TryLockError::Poisoned(e)) => self.apply(&e.into_inner()),
+            Err(TryLockError::WouldBlock) => Ok(()),
+        }
+    }
+
+    fn apply(&self, inbox: &Inbox<K, V>) -> Result<()> {
+        let actions = inbox.drain();
+        // Only take the write lock once there is something to apply, so readers aren't
+        // blocked by a drain that finds nothing
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let mut hashmap = self.hashmap.write().unwrap_or_else(PoisonError::into_inner);
+        let bloom = match self.bloom {
+            Some(ref bloom) => bloom,
+            None => {
+                for action in actions {
+                    action.apply_to(&mut hashmap, &mut |_| ());
+                }
+                return Ok(());
+            }
+        };
+        // Lookups read the filter without waiting on the lock, so a clear only resets it
+        // once everything has been applied, in case a key cleared was added back since
+        let mut reset: Option<Vec<u64>> = None;
+        for action in actions {
+            action.apply_to(&mut hashmap,
+                            &mut |change| match change {
+                                     ChangeRef::Added(k, _) => {
+                                         bloom.insert(k);
+                                         if let Some(ref mut words) = reset {
+                                             bloom.mark(words, k);
+                                         }
+                                     }
+                                     ChangeRef::Cleared => reset = Some(bloom.blank()),
+                                     _ => {}
+                                 });
+        }
+        if let Some(words) = reset {
+            bloom.reset(&words);
+        }
+        Ok(())
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMapSync<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    pub fn get<Q>(&self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.process_changes()?;
+        Ok(self.read().get(k).cloned())
+    }
+
+    /// Point-in-time copy of every entry, see `ReadOnlyHashMap::iter`
+    pub fn iter(&self) -> Result<Vec<(K, V)>> {
+        self.process_changes()?;
+        Ok(self.read()
+               .iter()
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+}
+
+/// Cheap, clonable handle onto the copies of a map published by a reader running on its
+/// own thread, see `ReadOnlyHashMap::into_arcswap`.  Lookups load the latest copy without
+/// taking any lock, so they never contend with each other or with a new copy going in.
+pub struct SnapshotReader<K, V, S = RandomState> {
+    current: Arc<ArcSwap<HashMap<K, V, S>>>,
+}
+
+impl<K, V, S> Clone for SnapshotReader<K, V, S> {
+    fn clone(&self) -> Self {
+        SnapshotReader { current: self.current.clone() }
+    }
+}
+
+impl<K, V, S> SnapshotReader<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// The latest copy published, which never changes once taken
+    pub fn load(&self) -> Arc<HashMap<K, V, S>> {
+        self.current.load_full()
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.current.load().contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.load().is_empty()
+    }
+}
+
+impl<K, V, S> SnapshotReader<K, V, S>
+    where K: Eq + Hash,
+          V: Clone,
+          S: BuildHasher
+{
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.current.load().get(k).cloned()
+    }
+}
+
+/// Cheap, clonable handle onto a reader running on its own thread, see
+/// `ReadOnlyHashMap::spawn_applier`.  Lookups only take a read lock, and see changes as
+/// soon as the background thread has applied them.
+pub struct SharedReader<K, V, S = RandomState> {
+    hashmap: Arc<RwLock<HashMap<K, V, S>>>,
+}
+
+impl<K, V, S> Clone for SharedReader<K, V, S> {
+    fn clone(&self) -> Self {
+        SharedReader { hashmap: self.hashmap.clone() }
+    }
+}
+
+impl<K, V, S> SharedReader<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.read().contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<K, V, S>> {
+        self.hashmap.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K, V, S> SharedReader<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.read().get(k).cloned()
+    }
+
+    /// Point-in-time copy of every entry
+    pub fn iter(&self) -> Vec<(K, V)> {
+        self.read()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
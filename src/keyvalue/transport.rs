@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use errors::ErrorKind::{Disconnected, WriterGone};
+use errors::Result;
+
+use super::{Action, Inbox, Outbox, ReadOnlyHashMap, Transmitter, WritableHashMap};
+
+
+/// Where a writer sends its changes, for carrying them over something other than the
+/// channels `keyvalue::new` sets up.  See `from_transport`.
+pub trait ChangeSink<K, V> {
+    /// Fails with `Disconnected` once nothing is left to receive the change
+    fn send(&self, action: Action<K, V>) -> Result<()>;
+}
+
+/// Where a reader takes its changes from, the far end of a `ChangeSink`
+pub trait ChangeSource<K, V> {
+    /// Takes every change that has arrived so far, in order, without waiting for more.
+    /// Fails with `WriterGone` once the writer is gone and nothing is left to take.
+    fn try_drain(&self) -> Result<Vec<Action<K, V>>>;
+}
+
+impl<K, V> ChangeSink<K, V> for Sender<Action<K, V>> {
+    fn send(&self, action: Action<K, V>) -> Result<()> {
+        Sender::send(self, action).map_err(|_| Disconnected.into())
+    }
+}
+
+impl<K, V> ChangeSource<K, V> for Receiver<Action<K, V>> {
+    fn try_drain(&self) -> Result<Vec<Action<K, V>>> {
+        let mut actions = Vec::new();
+        loop {
+            actions.extend(self.try_iter());
+            match self.try_recv() {
+                Ok(action) => actions.push(action),
+                Err(TryRecvError::Empty) => return Ok(actions),
+                Err(TryRecvError::Disconnected) if actions.is_empty() => {
+                    return Err(WriterGone.into())
+                }
+                Err(TryRecvError::Disconnected) => return Ok(actions),
+            }
+        }
+    }
+}
+
+/// Creates a pair that talks through `sink` and `source` instead of a channel of its own.
+/// Changes carried this way have no sequence numbers, so the reader's `applied_version`
+/// and `pending_len` stay at zero.
+///
+/// ```
+/// use ecds::keyvalue;
+/// use std::sync::mpsc::channel;
+/// let (tx, rx) = channel();
+/// let (mut w, r) = keyvalue::from_transport(tx, rx);
+/// w.insert(1, 1).unwrap();
+/// assert_eq!(Some(1), r.get(&1).unwrap());
+/// drop(w);
+/// assert!(r.refresh().is_err());
+/// ```
+pub fn from_transport<K, V, T, R>(sink: T, source: R)
+                                  -> (WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          T: ChangeSink<K, V> + Send + Sync + 'static,
+          R: ChangeSource<K, V> + Send + 'static
+{
+    (WritableHashMap::from_map(HashMap::new(),
+                               Outbox::new(vec![Transmitter::custom(Arc::new(sink))])),
+     ReadOnlyHashMap::from_map(HashMap::new(), Inbox::custom(Box::new(source))))
+}
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+use super::{Action, ReadOnlyHashMap};
+
+
+/// Decides which values a reader accepts, see `ReadOnlyHashMap::with_validator`
+pub(super) type Validator<K, V> = Box<dyn Fn(&K, &V) -> bool + Send>;
+
+/// Takes out of `action` every value `valid` turns away, counting them in `rejected`.
+/// The reader keeps what it held for those keys, so a `Replace` carries the old values
+/// over from `hashmap`, and a deadline later in the same batch isn't set on them.  Returns
+/// `None` if nothing is left to apply.
+pub(super) fn screen<K, V, S>(action: Action<K, V>,
+                              hashmap: &mut HashMap<K, V, S>,
+                              valid: &dyn Fn(&K, &V) -> bool,
+                              rejected: &mut u64)
+                              -> Option<Action<K, V>>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    screen_keys(action, hashmap, valid, rejected, &mut HashSet::new())
+}
+
+/// `screen`, adding the keys turned away to `kept`, or taking them back out once a later
+/// value for them is let through
+fn screen_keys<K, V, S>(action: Action<K, V>,
+                        hashmap: &mut HashMap<K, V, S>,
+                        valid: &dyn Fn(&K, &V) -> bool,
+                        rejected: &mut u64,
+                        kept: &mut HashSet<K>)
+                        -> Option<Action<K, V>>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    let mut keep = |k: &K, v: &V, kept: &mut HashSet<K>| {
+        let keep = valid(k, v);
+        if keep {
+            kept.remove(k);
+        } else {
+            *rejected += 1;
+        }
+        keep
+    };
+    match action {
+        Action::Add(k, v) => {
+            if keep(&k, &v, kept) {
+                Some(Action::Add(k, v))
+            } else {
+                kept.insert(k);
+                None
+            }
+        }
+        Action::AddMany(pairs) => {
+            let mut accepted = Vec::with_capacity(pairs.len());
+            for (k, v) in pairs {
+                if keep(&k, &v, kept) {
+                    accepted.push((k, v));
+                } else {
+                    kept.insert(k);
+                }
+            }
+            Some(Action::AddMany(accepted))
+        }
+        Action::Replace(pairs) => {
+            let mut accepted = Vec::with_capacity(pairs.len());
+            for (k, v) in pairs {
+                if keep(&k, &v, kept) {
+                    accepted.push((k, v));
+                } else {
+                    // The replace clears the map anyway, so the old value can be moved over
+                    if let Some(old) = hashmap.remove_entry(&k) {
+                        accepted.push(old);
+                    }
+                    kept.insert(k);
+                }
+            }
+            Some(Action::Replace(accepted))
+        }
+        Action::Expire(k, at) => {
+            if kept.contains(&k) {
+                None
+            } else {
+                Some(Action::Expire(k, at))
+            }
+        }
+        Action::Batch(actions) => {
+            Some(Action::Batch(actions
+                                   .into_iter()
+                                   .filter_map(|action| {
+                                                   screen_keys(action,
+                                                               hashmap,
+                                                               valid,
+                                                               rejected,
+                                                               kept)
+                                               })
+                                   .collect()))
+        }
+        action => Some(action),
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          S: BuildHasher
+{
+    /// Only stores the values sent by the writer that pass `f`, leaving whatever the
+    /// reader held for the key before.  Each one turned away is counted in the stats as
+    /// `rejected`.  Removals and clears always apply, and neither are deltas sent by
+    /// `update` checked.  For each value turned away this reader knowingly stops matching
+    /// the writer.  That includes a `resync`, and a time to live sent along with the value
+    /// is turned away with it.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// let (mut w, r) = keyvalue::new();
+    /// let r = r.with_validator(|_, v: &i32| *v >= 0);
+    /// w.insert("a", 1).unwrap();
+    /// w.insert("a", -1).unwrap();
+    /// w.insert("b", -1).unwrap();
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// assert_eq!(None, r.get("b").unwrap());
+    /// assert_eq!(2, r.stats().unwrap().rejected);
+    /// w.remove("a").unwrap();
+    /// assert!(r.is_empty().unwrap());
+    ///
+    /// w.insert("a", 1).unwrap();
+    /// w.insert_with_ttl("a", -1, Duration::from_millis(10)).unwrap();
+    /// thread::sleep(Duration::from_millis(20));
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// w.resync().unwrap();
+    /// assert_eq!(Some(1), r.get("a").unwrap());
+    /// ```
+    pub fn with_validator<F>(self, f: F) -> Self
+        where F: Fn(&K, &V) -> bool + Send + 'static
+    {
+        self.hooks.borrow_mut().validator = Some(Box::new(f));
+        self
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use errors::{Error, ErrorKind, Result};
+
+use super::{Change, ReadOnlyHashMap, Rx};
+
+
+/// Where a reader waiting in `next_changes` leaves its waker for the writer, shared by a
+/// reader's inbox and every transmitter feeding it
+pub(super) struct WakeSlot {
+    /// Set while a waker is stored, so sending doesn't need the lock otherwise
+    waiting: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl WakeSlot {
+    pub(super) fn new() -> Self {
+        WakeSlot {
+            waiting: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        *self.waker.lock().unwrap_or_else(PoisonError::into_inner) = Some(waker.clone());
+        self.waiting.store(true, Ordering::SeqCst);
+    }
+
+    /// Wakes the reader if it is waiting
+    pub(super) fn wake(&self) {
+        if self.waiting.swap(false, Ordering::SeqCst) {
+            let waker = self.waker.lock().unwrap_or_else(PoisonError::into_inner).take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A transmitter's hold on its reader's slot, which wakes the reader once dropped so it
+/// hears that the writer is gone
+pub(super) struct WakeOnDrop(pub(super) Arc<WakeSlot>);
+
+impl Drop for WakeOnDrop {
+    fn drop(&mut self) {
+        self.0.wake();
+    }
+}
+
+/// Resolves to the next changes a reader applies, see `ReadOnlyHashMap::next_changes`
+pub struct NextChanges<'a, K: 'a, V: 'a, S: 'a> {
+    reader: &'a ReadOnlyHashMap<K, V, S>,
+}
+
+impl<'a, K, V, S> Future for NextChanges<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    type Output = Result<Vec<Change<K, V>>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_changes(self.reader, cx)
+    }
+}
+
+/// Applies whatever has arrived and hands back the changes made, or leaves the waker to
+/// hear about the next ones
+fn poll_changes<K, V, S>(reader: &ReadOnlyHashMap<K, V, S>,
+                         cx: &mut Context<'_>)
+                         -> Poll<Result<Vec<Change<K, V>>>>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    match reader.drain_changes() {
+        Ok(ref changes) if changes.is_empty() => {}
+        changes => return Poll::Ready(changes),
+    }
+    reader.inbox.wake.register(cx.waker());
+    // Something may have been sent after draining but before the waker was left, and a
+    // custom source can't wake anyone, so look again straight away
+    if reader.pending_len() > 0 || reader.inbox.writer_gone() ||
+       matches!(reader.inbox.rx, Rx::Custom(_)) {
+        cx.waker().wake_by_ref();
+    }
+    Poll::Pending
+}
+
+/// Every change a reader applies, one at a time, see `ReadOnlyHashMap::stream_changes`
+pub struct ChangeStream<'a, K: 'a, V: 'a, S: 'a> {
+    reader: &'a ReadOnlyHashMap<K, V, S>,
+    /// Changes already applied together with an earlier one, still to be handed out
+    applied: VecDeque<Change<K, V>>,
+    done: bool,
+}
+
+// Nothing in it is ever pinned in place
+impl<'a, K, V, S> Unpin for ChangeStream<'a, K, V, S> {}
+
+impl<'a, K, V, S> Stream for ChangeStream<'a, K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    type Item = Result<Change<K, V>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(change) = this.applied.pop_front() {
+            return Poll::Ready(Some(Ok(change)));
+        }
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match poll_changes(this.reader, cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(changes)) => {
+                this.applied.extend(changes);
+                Poll::Ready(this.applied.pop_front().map(Ok))
+            }
+            Poll::Ready(Err(Error(ErrorKind::WriterGone, _))) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Waits, without blocking the thread, for the writer to send something, then applies
+    /// it and resolves to the changes made, like `drain_changes`.  Resolves right away
+    /// if changes are already pending, and fails with `WriterGone` once the writer is gone
+    /// and everything it sent has been returned.  A reader fed by `from_transport` has no
+    /// way to be woken, so it keeps being polled instead.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change};
+    /// use std::future::Future;
+    /// use std::pin::Pin;
+    /// use std::sync::Arc;
+    /// use std::task::{Context, Poll, Wake};
+    /// use std::thread::{self, Thread};
+    /// use std::time::Duration;
+    ///
+    /// struct Unpark(Thread);
+    /// impl Wake for Unpark {
+    ///     fn wake(self: Arc<Self>) {
+    ///         self.0.unpark()
+    ///     }
+    /// }
+    ///
+    /// fn block_on<F: Future + Unpin>(mut f: F) -> F::Output {
+    ///     let waker = Arc::new(Unpark(thread::current())).into();
+    ///     let mut cx = Context::from_waker(&waker);
+    ///     loop {
+    ///         match Pin::new(&mut f).poll(&mut cx) {
+    ///             Poll::Ready(output) => return output,
+    ///             Poll::Pending => thread::park(),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (mut w, r) = keyvalue::new();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     w.insert(1, 1).unwrap();
+    ///     thread::sleep(Duration::from_millis(10));
+    /// });
+    /// assert_eq!(vec![Change::Added { key: 1, value: 1 }],
+    ///            block_on(r.next_changes()).unwrap());
+    /// assert!(block_on(r.next_changes()).is_err());
+    /// ```
+    pub fn next_changes(&self) -> NextChanges<'_, K, V, S> {
+        NextChanges { reader: self }
+    }
+
+    /// A `Stream` of every change this reader applies from now on, one per item, ending
+    /// once the writer is gone and everything it sent has been handed out.  Changes are
+    /// applied as the stream is polled, a whole batch of arrivals at a time, so by the time
+    /// one is yielded the reader already reflects it.
+    ///
+    /// ```
+    /// extern crate ecds;
+    /// extern crate futures_core;
+    /// use ecds::keyvalue::{self, Change};
+    /// use futures_core::Stream;
+    /// use std::pin::Pin;
+    /// use std::sync::Arc;
+    /// use std::task::{Context, Poll, Wake};
+    /// use std::thread::{self, Thread};
+    /// use std::time::Duration;
+    ///
+    /// struct Unpark(Thread);
+    /// impl Wake for Unpark {
+    ///     fn wake(self: Arc<Self>) {
+    ///         self.0.unpark()
+    ///     }
+    /// }
+    ///
+    /// fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+    ///     let waker = Arc::new(Unpark(thread::current())).into();
+    ///     let mut cx = Context::from_waker(&waker);
+    ///     loop {
+    ///         match Pin::new(&mut *stream).poll_next(&mut cx) {
+    ///             Poll::Ready(item) => return item,
+    ///             Poll::Pending => thread::park(),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (mut w, r) = keyvalue::new();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     w.insert(1, 1).unwrap();
+    ///     w.insert(2, 2).unwrap();
+    ///     w.remove(&1).unwrap();
+    /// });
+    /// let mut changes = r.stream_changes();
+    /// let mut seen = Vec::new();
+    /// while let Some(change) = next(&mut changes) {
+    ///     seen.push(change.unwrap());
+    /// }
+    /// assert_eq!(vec![Change::Added { key: 1, value: 1 },
+    ///                 Change::Added { key: 2, value: 2 },
+    ///                 Change::Removed { key: 1 }],
+    ///            seen);
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// ```
+    pub fn stream_changes(&self) -> ChangeStream<'_, K, V, S> {
+        ChangeStream {
+            reader: self,
+            applied: VecDeque::new(),
+            done: false,
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use errors::Result;
+
+use super::{seeded, Action, Change, ReadOnlyHashMap, WritableHashMap};
+
+
+/// How changes are turned into bytes for a log and back, see `WritableHashMap::with_wal`.
+/// The log takes care of framing, so each call only ever sees a single change.
+pub trait Codec<K, V> {
+    fn encode(&self, change: &Change<K, V>, out: &mut Vec<u8>) -> io::Result<()>;
+    fn decode(&self, bytes: &[u8]) -> io::Result<Change<K, V>>;
+}
+
+/// Append-only log of the changes a writer sends.  Every action is written as one
+/// length-prefixed frame holding its changes, so a batch is replayed all or nothing.
+pub(super) struct Wal<K, V> {
+    file: File,
+    codec: Box<dyn Codec<K, V> + Send>,
+}
+
+impl<K: Clone, V: Clone> Wal<K, V> {
+    /// Opens the log at `path` for appending, returning what it already holds.  A frame
+    /// left half written by a crash is cut off so new frames follow the last intact one.
+    fn open<P, C>(path: P, codec: C) -> Result<(Self, Vec<u8>)>
+        where P: AsRef<Path>,
+              C: Codec<K, V> + Send + 'static
+    {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+        let intact = frames(&log).map(|frame| frame.len() + 4).sum();
+        log.truncate(intact);
+        file.set_len(intact as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok((Wal {
+                file,
+                codec: Box::new(codec),
+            },
+            log))
+    }
+
+    /// Writes `action` to the log, returning where its frame starts so it can be taken
+    /// back with `truncate`.  Nothing is written if any change fails to encode.
+    pub(super) fn append(&mut self, action: &Action<K, V>) -> Result<u64> {
+        let start = self.file.stream_position()?;
+        let mut changes = Vec::new();
+        action.changes(&mut changes);
+        write_frame(&*self.codec, &changes, &mut self.file)?;
+        Ok(start)
+    }
+
+    /// Cuts the log off at `len`, dropping every frame written from there on
+    pub(super) fn truncate(&mut self, len: u64) -> Result<()> {
+        self.file.set_len(len)?;
+        self.file.seek(SeekFrom::Start(len))?;
+        Ok(())
+    }
+
+    /// Applies every change in `log` to `hashmap`
+    fn replay<S>(&self, log: &[u8], hashmap: &mut HashMap<K, V, S>) -> Result<()>
+        where K: Eq + Hash,
+              S: BuildHasher
+    {
+        for frame in frames(log) {
+            // Decode the whole frame up front so a bad one isn't half applied
+            for change in decode_frame(&*self.codec, frame)? {
+                Action::from(change).apply_to(hashmap, &mut |_| ());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Clone, V: Clone> Action<K, V> {
+    /// How this action is written out.  Updates are never written, see
+    /// `WritableHashMap::update`, and deadlines are only meaningful to the running process.
+    pub(super) fn changes(&self, out: &mut Vec<Change<K, V>>) {
+        match *self {
+            Action::Add(ref k, ref v) => {
+                out.push(Change::Added {
+                             key: k.clone(),
+                             value: v.clone(),
+                         })
+            }
+            Action::Replace(ref pairs) => {
+                out.push(Change::Cleared);
+                Action::AddMany(pairs.clone()).changes(out);
+            }
+            Action::AddMany(ref pairs) => {
+                out.extend(pairs.iter().map(|(k, v)| {
+                                                Change::Added {
+                                                    key: k.clone(),
+                                                    value: v.clone(),
+                                                }
+                                            }))
+            }
+            Action::Remove(ref k) => out.push(Change::Removed { key: k.clone() }),
+            Action::RemoveMany(ref keys) => {
+                out.extend(keys.iter().map(|k| Change::Removed { key: k.clone() }))
+            }
+            Action::Clear | Action::ClearKeepCapacity(_) => out.push(Change::Cleared),
+            Action::Update(..) | Action::Expire(..) | Action::Reserve(_) | Action::Shrink => {}
+            Action::Batch(ref actions) => {
+                for action in actions {
+                    action.changes(out);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `changes` as one length-prefixed frame, each change framed again inside it.
+/// Nothing is written if any of them fails to encode.
+pub(super) fn write_frame<K, V, C, W>(codec: &C, changes: &[Change<K, V>], w: &mut W) -> io::Result<()>
+    where C: ?Sized + Codec<K, V>,
+          W: Write
+{
+    let mut frame = vec![0; 4];
+    for change in changes {
+        let start = frame.len();
+        frame.extend_from_slice(&[0; 4]);
+        codec.encode(change, &mut frame)?;
+        let len = (frame.len() - start - 4) as u32;
+        frame[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+    let len = (frame.len() - 4) as u32;
+    frame[..4].copy_from_slice(&len.to_le_bytes());
+    w.write_all(&frame)
+}
+
+/// Reads the next frame written by `write_frame`, or `None` at a clean end of stream
+#[cfg(feature = "net")]
+pub(super) fn read_frame<K, V, C, R>(codec: &C, r: &mut R) -> io::Result<Option<Vec<Change<K, V>>>>
+    where C: ?Sized + Codec<K, V>,
+          R: Read
+{
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut frame = vec![0; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut frame)?;
+    decode_frame(codec, &frame).map(Some)
+}
+
+fn decode_frame<K, V, C>(codec: &C, frame: &[u8]) -> io::Result<Vec<Change<K, V>>>
+    where C: ?Sized + Codec<K, V>
+{
+    frames(frame).map(|bytes| codec.decode(bytes)).collect()
+}
+
+/// Splits `bytes` into length-prefixed frames, stopping at the first incomplete one
+fn frames(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    ::std::iter::from_fn(move || {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let mut len = [0; 4];
+        len.copy_from_slice(&bytes[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if bytes.len() - 4 < len {
+            return None;
+        }
+        let (frame, rest) = bytes[4..].split_at(len);
+        bytes = rest;
+        Some(frame)
+    })
+}
+
+/// Rebuilds a pair from the log at `path`, see `WritableHashMap::with_wal`.  The writer
+/// keeps appending to the same log, and the reader picks up live changes from there as
+/// usual.  A missing log is treated as empty.
+pub fn from_wal<K, V, P, C>(path: P,
+                            codec: C)
+                            -> Result<(WritableHashMap<K, V>, ReadOnlyHashMap<K, V>)>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          P: AsRef<Path>,
+          C: Codec<K, V> + Send + 'static
+{
+    let (wal, log) = Wal::open(path, codec)?;
+    let mut hashmap = HashMap::new();
+    wal.replay(&log, &mut hashmap)?;
+    let (mut w, r) = seeded(hashmap);
+    w.outbox.wal = Some(Arc::new(Mutex::new(wal)));
+    Ok((w, r))
+}
+
+impl<K, V, S> WritableHashMap<K, V, S>
+    where K: Eq + Hash + Clone,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Writes every change to the log at `path` before sending it, so the map can be
+    /// rebuilt with `from_wal` after a restart.  The log starts from the current contents
+    /// of the map, superseding anything already in it.
+    ///
+    /// A change that can't be written out isn't sent either, so readers never see
+    /// anything the log is missing.  One that reaches no reader, say because a bounded
+    /// channel was full, is taken back out of the log, so a write that failed is never
+    /// replayed.  Writes aren't synced to disk, so they survive the process crashing but
+    /// not the machine.
+    ///
+    /// ```
+    /// use ecds::keyvalue::{self, Change, Codec};
+    /// use std::io;
+    ///
+    /// struct Bytes;
+    /// impl Codec<u8, u8> for Bytes {
+    ///     fn encode(&self, change: &Change<u8, u8>, out: &mut Vec<u8>) -> io::Result<()> {
+    ///         match *change {
+    ///             Change::Added { key, value } => out.extend_from_slice(&[0, key, value]),
+    ///             Change::Removed { key } => out.extend_from_slice(&[1, key]),
+    ///             Change::Cleared => out.push(2),
+    ///         }
+    ///         Ok(())
+    ///     }
+    ///     fn decode(&self, bytes: &[u8]) -> io::Result<Change<u8, u8>> {
+    ///         match bytes {
+    ///             [0, key, value] => Ok(Change::Added { key: *key, value: *value }),
+    ///             [1, key] => Ok(Change::Removed { key: *key }),
+    ///             [2] => Ok(Change::Cleared),
+    ///             _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad change")),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let path = std::env::temp_dir().join(format!("ecds-wal-{}", std::process::id()));
+    /// let (w, _r) = keyvalue::from_iter(vec![(1, 1)]);
+    /// let mut w = w.with_wal(&path, Bytes).unwrap();
+    /// w.insert(2, 2).unwrap();
+    /// w.remove(&1).unwrap();
+    /// drop(w);
+    ///
+    /// let (mut w, r) = keyvalue::from_wal(&path, Bytes).unwrap();
+    /// assert_eq!(vec![(2, 2)], r.iter().unwrap());
+    /// w.insert(3, 3).unwrap();
+    /// assert_eq!(Some(3), r.get(&3).unwrap());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    ///
+    /// ```
+    /// # use ecds::keyvalue::{self, Change, Codec};
+    /// # use std::io;
+    /// # struct Bytes;
+    /// # impl Codec<u8, u8> for Bytes {
+    /// #     fn encode(&self, change: &Change<u8, u8>, out: &mut Vec<u8>) -> io::Result<()> {
+    /// #         match *change {
+    /// #             Change::Added { key, value } => out.extend_from_slice(&[0, key, value]),
+    /// #             Change::Removed { key } => out.extend_from_slice(&[1, key]),
+    /// #             Change::Cleared => out.push(2),
+    /// #         }
+    /// #         Ok(())
+    /// #     }
+    /// #     fn decode(&self, bytes: &[u8]) -> io::Result<Change<u8, u8>> {
+    /// #         match bytes {
+    /// #             [0, key, value] => Ok(Change::Added { key: *key, value: *value }),
+    /// #             [1, key] => Ok(Change::Removed { key: *key }),
+    /// #             [2] => Ok(Change::Cleared),
+    /// #             _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad change")),
+    /// #         }
+    /// #     }
+    /// # }
+    /// let path = std::env::temp_dir().join(format!("ecds-wal-full-{}", std::process::id()));
+    /// let (w, _r) = keyvalue::bounded(1);
+    /// let mut w = w.with_wal(&path, Bytes).unwrap();
+    /// w.insert_nonblocking(1, 1).unwrap();
+    /// assert!(w.insert_nonblocking(2, 2).is_err());
+    /// assert_eq!(1, w.version());
+    /// drop(w);
+    ///
+    /// let (_w, r) = keyvalue::from_wal(&path, Bytes).unwrap();
+    /// assert_eq!(vec![(1, 1)], r.iter().unwrap());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn with_wal<P, C>(mut self, path: P, codec: C) -> Result<Self>
+        where P: AsRef<Path>,
+              C: Codec<K, V> + Send + 'static
+    {
+        let (mut wal, _) = Wal::open(path, codec)?;
+        let mut snapshot = vec![Action::Clear];
+        snapshot.extend(self.hashmap
+                            .iter()
+                            .map(|(k, v)| Action::Add(k.clone(), v.clone())));
+        wal.append(&Action::Batch(snapshot))?;
+        self.outbox.wal = Some(Arc::new(Mutex::new(wal)));
+        Ok(self)
+    }
+}
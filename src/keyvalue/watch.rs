@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use errors::{Error, Result};
+use errors::ErrorKind::Reentrant;
+
+use super::{ChangeRef, ReadOnlyHashMap};
+
+
+/// Receives the changes a reader applies to one key, see `ReadOnlyHashMap::watch`
+pub struct WatchHandle<V> {
+    rx: Receiver<Option<V>>,
+}
+
+impl<V> WatchHandle<V> {
+    /// The value the next change not yet seen left the key with, or `None` inside if it
+    /// was removed.  Returns `None` when there is nothing new.
+    pub fn try_recv(&self) -> Option<Option<V>> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Every live `WatchHandle` on a reader, by key
+pub(super) struct Watches<K, V> {
+    senders: HashMap<K, Vec<Sender<Option<V>>>>,
+    /// `V::clone`, kept here since readers apply changes without needing `Clone`
+    clone: fn(&V) -> V,
+}
+
+impl<K: Eq + Hash, V> Watches<K, V> {
+    /// Signals the watches on whatever key `change` touched, forgetting any whose handle
+    /// has been dropped
+    pub(super) fn observe(&mut self, change: &ChangeRef<'_, K, V>) {
+        match *change {
+            ChangeRef::Added(k, v) |
+            ChangeRef::Updated(k, v) => self.signal(k, Some(v)),
+            ChangeRef::Removed(k) => self.signal(k, None),
+            ChangeRef::Cleared => {
+                self.senders.retain(|_, senders| {
+                                        senders.retain(|tx| tx.send(None).is_ok());
+                                        !senders.is_empty()
+                                    })
+            }
+            ChangeRef::Expires(..) => {}
+        }
+    }
+
+    fn signal(&mut self, k: &K, v: Option<&V>) {
+        let clone = self.clone;
+        let forgotten = match self.senders.get_mut(k) {
+            Some(senders) => {
+                senders.retain(|tx| tx.send(v.map(clone)).is_ok());
+                senders.is_empty()
+            }
+            None => return,
+        };
+        if forgotten {
+            self.senders.remove(k);
+        }
+    }
+}
+
+impl<K, V, S> ReadOnlyHashMap<K, V, S>
+    where K: Eq + Hash,
+          V: Clone,
+          S: BuildHasher
+{
+    /// Signals the returned handle each time this reader applies a change to `key`,
+    /// including one made by a time to live or `with_capacity_limit`.  A clear signals
+    /// every watch, whether or not its key was present.  Once the handle is dropped the
+    /// watch is forgotten at the next change to its key.  Fails with `Reentrant` when
+    /// called from one of this reader's callbacks.
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::new();
+    /// let watch = r.watch("a").unwrap();
+    /// w.insert("a", 1).unwrap();
+    /// w.insert("b", 2).unwrap();
+    /// w.remove("a").unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(Some(Some(1)), watch.try_recv());
+    /// assert_eq!(Some(None), watch.try_recv());
+    /// assert_eq!(None, watch.try_recv());
+    /// ```
+    pub fn watch(&self, key: K) -> Result<WatchHandle<V>> {
+        let mut hooks = self.hooks.try_borrow_mut().map_err(|_| Error::from(Reentrant))?;
+        let (tx, rx) = channel();
+        hooks.watches
+            .get_or_insert_with(|| {
+                                    Watches {
+                                        senders: HashMap::new(),
+                                        clone: V::clone,
+                                    }
+                                })
+            .senders
+            .entry(key)
+            .or_default()
+            .push(tx);
+        Ok(WatchHandle { rx })
+    }
+
+    /// Applies pending changes, then returns the value of `key` along with a watch on it,
+    /// so every change from that value on reaches the handle
+    ///
+    /// ```
+    /// use ecds::keyvalue;
+    /// let (mut w, r) = keyvalue::from_iter(vec![("a", 1)]);
+    /// let (value, watch) = r.get_or_subscribe("a").unwrap();
+    /// assert_eq!(Some(1), value);
+    /// w.insert("a", 2).unwrap();
+    /// r.refresh().unwrap();
+    /// assert_eq!(Some(Some(2)), watch.try_recv());
+    /// ```
+    pub fn get_or_subscribe(&self, key: K) -> Result<(Option<V>, WatchHandle<V>)> {
+        self.process_changes()?;
+        // Nothing is applied between reading the value and setting up the watch
+        let value = self.hashmap.borrow().get(&key).cloned();
+        Ok((value, self.watch(key)?))
+    }
+}
@@ -1,7 +1,14 @@
+extern crate arc_swap;
 #[macro_use]
 extern crate error_chain;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 pub mod keyvalue;
+pub mod ordered;
+pub mod set;
 pub mod errors;
 
 
@@ -0,0 +1,315 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+use errors::Result;
+use keyvalue::{open, Action, Inbox, Outbox};
+
+
+/// Creates a pair like `keyvalue::new`, but backed by a `BTreeMap` so entries come back in
+/// key order and can be scanned by range
+///
+/// ```
+/// use ecds::ordered;
+/// let (mut w, r) = ordered::new();
+/// for t in 0..10 {
+///     w.insert(t, t * 10).unwrap();
+/// }
+/// assert_eq!(vec![(3, 30), (4, 40)], r.range(3..5).unwrap());
+/// assert_eq!(Some((0, 0)), r.iter().unwrap().into_iter().next());
+/// ```
+pub fn new<K: Ord + Clone, V: Clone>() -> (WritableBTreeMap<K, V>, ReadOnlyBTreeMap<K, V>) {
+    let (tx, inbox) = open(None);
+    (WritableBTreeMap {
+         btreemap: BTreeMap::new(),
+         outbox: Outbox::new(vec![tx]),
+     },
+     ReadOnlyBTreeMap {
+         btreemap: RefCell::new(BTreeMap::new()),
+         inbox,
+     })
+}
+
+/// Applies an action the same way `keyvalue` readers do
+fn apply<K: Ord, V>(action: Action<K, V>, btreemap: &mut BTreeMap<K, V>) {
+    match action {
+        Action::Add(k, v) => {
+            btreemap.insert(k, v);
+        }
+        Action::AddMany(pairs) => btreemap.extend(pairs),
+        Action::Update(k, delta) => {
+            if let Some(v) = btreemap.get_mut(&k) {
+                delta.apply(v);
+            }
+        }
+        Action::Remove(k) => {
+            btreemap.remove(&k);
+        }
+        Action::RemoveMany(keys) => {
+            for k in keys {
+                btreemap.remove(&k);
+            }
+        }
+        Action::Clear |
+        Action::ClearKeepCapacity(_) => btreemap.clear(),
+        Action::Replace(pairs) => {
+            btreemap.clear();
+            btreemap.extend(pairs);
+        }
+        // This writer never sets a time to live or sends capacity hints
+        Action::Expire(..) |
+        Action::Reserve(_) |
+        Action::Shrink => {}
+        Action::Batch(actions) => {
+            for action in actions {
+                apply(action, btreemap);
+            }
+        }
+    }
+}
+
+/// Keys that `ReadOnlyBTreeMap::prefix_scan` can match by their start
+pub trait Prefix {
+    fn starts_with(&self, prefix: &Self) -> bool;
+}
+
+impl Prefix for str {
+    fn starts_with(&self, prefix: &str) -> bool {
+        str::starts_with(self, prefix)
+    }
+}
+
+impl<T: PartialEq> Prefix for [T] {
+    fn starts_with(&self, prefix: &[T]) -> bool {
+        <[T]>::starts_with(self, prefix)
+    }
+}
+
+pub struct WritableBTreeMap<K, V> {
+    btreemap: BTreeMap<K, V>,
+    outbox: Outbox<K, V>,
+}
+
+impl<K: Ord + Clone, V: Clone> WritableBTreeMap<K, V> {
+    pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>> {
+        self.outbox.send(Action::Add(k.clone(), v.clone()), true)?;
+        Ok(self.btreemap.insert(k, v))
+    }
+
+    /// Removes a key, sending nothing when it isn't present
+    pub fn remove<Q>(&mut self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        let key = match self.btreemap.get_key_value(k) {
+            Some((key, _)) => key.clone(),
+            None => return Ok(None),
+        };
+        self.outbox.send(Action::Remove(key), true)?;
+        Ok(self.btreemap.remove(k))
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.outbox.send(Action::Clear, true)?;
+        self.btreemap.clear();
+        Ok(())
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.btreemap.get(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.btreemap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.btreemap.is_empty()
+    }
+}
+
+pub struct ReadOnlyBTreeMap<K, V> {
+    btreemap: RefCell<BTreeMap<K, V>>,
+    inbox: Inbox<K, V>,
+}
+
+impl<K: Ord, V> ReadOnlyBTreeMap<K, V> {
+    pub fn contains_key<Q>(&self, k: &Q) -> Result<bool>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.process_changes()?;
+        Ok(self.btreemap.borrow().contains_key(k))
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.process_changes()?;
+        Ok(self.btreemap.borrow().len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.process_changes()?;
+        Ok(self.btreemap.borrow().is_empty())
+    }
+
+    /// See `ReadOnlyHashMap::refresh`
+    pub fn refresh(&self) -> Result<()> {
+        self.process_changes()?;
+        self.inbox.check_open()
+    }
+
+    fn process_changes(&self) -> Result<()> {
+        let actions = self.inbox.drain();
+        if !actions.is_empty() {
+            let mut btreemap = self.btreemap.borrow_mut();
+            for action in actions {
+                apply(action, &mut btreemap);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> ReadOnlyBTreeMap<K, V> {
+    pub fn get<Q>(&self, k: &Q) -> Result<Option<V>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord
+    {
+        self.process_changes()?;
+        Ok(self.btreemap.borrow().get(k).cloned())
+    }
+
+    /// Point-in-time copy of every entry, in key order
+    pub fn iter(&self) -> Result<Vec<(K, V)>> {
+        self.process_changes()?;
+        Ok(self.btreemap
+               .borrow()
+               .iter()
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+
+    /// Point-in-time copy of the entries with keys in `range`, in key order
+    pub fn range<Q, R>(&self, range: R) -> Result<Vec<(K, V)>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord,
+              R: RangeBounds<Q>
+    {
+        self.process_changes()?;
+        Ok(self.btreemap
+               .borrow()
+               .range(range)
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+
+    /// Point-in-time copy of the entries whose keys start with `prefix`, in key order.
+    /// Only those entries are visited, rather than every one as with a filter.  Works for
+    /// any key that borrows as a `str` or a slice, such as `String` or `Vec<u8>`.
+    ///
+    /// ```
+    /// use ecds::ordered;
+    /// let (mut w, r) = ordered::new();
+    /// for k in vec!["app", "apple", "apply", "banana"] {
+    ///     w.insert(k.to_string(), k.len()).unwrap();
+    /// }
+    /// assert_eq!(vec![("apple".to_string(), 5), ("apply".to_string(), 5)],
+    ///            r.prefix_scan("appl").unwrap());
+    /// assert_eq!(4, r.prefix_scan("").unwrap().len());
+    /// assert!(r.prefix_scan("cherry").unwrap().is_empty());
+    ///
+    /// let (mut w, r) = ordered::new();
+    /// w.insert(vec![1u8, 2], "a").unwrap();
+    /// w.insert(vec![1u8, 3], "b").unwrap();
+    /// assert_eq!(vec![(vec![1, 2], "a")], r.prefix_scan(&[1u8, 2][..]).unwrap());
+    /// ```
+    pub fn prefix_scan<Q>(&self, prefix: &Q) -> Result<Vec<(K, V)>>
+        where K: Borrow<Q>,
+              Q: ?Sized + Ord + Prefix
+    {
+        self.process_changes()?;
+        Ok(self.btreemap
+               .borrow()
+               .range::<Q, _>((Bound::Included(prefix), Bound::Unbounded))
+               .take_while(|(k, _)| (*k).borrow().starts_with(prefix))
+               .map(|(k, v)| (k.clone(), v.clone()))
+               .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new;
+
+    fn scan(keys: &[&str], prefix: &str) -> Vec<String> {
+        let (mut w, r) = new();
+        for k in keys {
+            w.insert(k.to_string(), ()).unwrap();
+        }
+        r.prefix_scan(prefix)
+            .unwrap()
+            .into_iter()
+            .map(|(k, ())| k)
+            .collect()
+    }
+
+    #[test]
+    fn prefix_scan_includes_the_prefix_itself() {
+        assert_eq!(vec!["ab", "abc"], scan(&["a", "ab", "abc", "b"], "ab"));
+    }
+
+    #[test]
+    fn prefix_scan_stops_at_the_first_key_past_the_prefix() {
+        assert_eq!(vec!["ab", "ab\u{0}", "ab~"],
+                   scan(&["aa", "ab", "ab\u{0}", "ab~", "ac", "b"], "ab"));
+        assert!(scan(&["aa", "ac"], "ab").is_empty());
+    }
+
+    #[test]
+    fn prefix_scan_with_an_empty_prefix_returns_everything() {
+        assert_eq!(vec!["", "a", "b"], scan(&["b", "", "a"], ""));
+    }
+
+    #[test]
+    fn prefix_scan_longer_than_every_key() {
+        assert!(scan(&["a", "ab"], "abc").is_empty());
+    }
+
+    #[test]
+    fn prefix_scan_past_the_last_key() {
+        assert!(scan(&["a", "b"], "c").is_empty());
+    }
+
+    #[test]
+    fn prefix_scan_matches_whole_characters() {
+        assert_eq!(vec!["é", "éa"], scan(&["e", "é", "éa", "f"], "é"));
+    }
+
+    #[test]
+    fn prefix_scan_over_byte_keys() {
+        let (mut w, r) = new();
+        for k in &[vec![0u8], vec![1], vec![1, 0], vec![1, 255], vec![2]] {
+            w.insert(k.clone(), ()).unwrap();
+        }
+        let keys: Vec<Vec<u8>> = r.prefix_scan(&[1u8][..])
+            .unwrap()
+            .into_iter()
+            .map(|(k, ())| k)
+            .collect();
+        assert_eq!(vec![vec![1], vec![1, 0], vec![1, 255]], keys);
+    }
+
+    #[test]
+    fn prefix_scan_sees_pending_changes() {
+        let (mut w, r) = new();
+        w.insert("ab".to_string(), 1).unwrap();
+        assert_eq!(1, r.prefix_scan("a").unwrap().len());
+        w.insert("ac".to_string(), 2).unwrap();
+        w.remove("ab").unwrap();
+        assert_eq!(vec![("ac".to_string(), 2)], r.prefix_scan("a").unwrap());
+    }
+}
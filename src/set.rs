@@ -0,0 +1,105 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use errors::Result;
+use keyvalue::{self, ReadOnlyHashMap, WritableHashMap};
+
+
+/// Creates a set pair, see `keyvalue::new`
+///
+/// ```
+/// use ecds::set;
+/// let (mut w, r) = set::new();
+/// assert!(w.insert("a").unwrap());
+/// assert!(!w.insert("a").unwrap());
+/// w.insert("b").unwrap();
+/// w.remove("a").unwrap();
+/// assert!(r.contains("b").unwrap());
+/// assert!(!r.contains("a").unwrap());
+/// assert_eq!(1, r.len().unwrap());
+/// ```
+pub fn new<T: Eq + Hash + Clone>() -> (WritableHashSet<T>, ReadOnlyHashSet<T>) {
+    let (w, r) = keyvalue::set_like();
+    (WritableHashSet { inner: w }, ReadOnlyHashSet { inner: r })
+}
+
+/// The writing half of a set, a `WritableHashMap` with no values.  `()` takes no space
+/// and costs nothing to clone, so this stores and sends exactly what a `HashSet` would;
+/// `HashSet` itself is a `HashMap` with `()` values.
+pub struct WritableHashSet<T, S = RandomState> {
+    inner: WritableHashMap<T, (), S>,
+}
+
+impl<T, S> WritableHashSet<T, S>
+    where T: Eq + Hash + Clone,
+          S: BuildHasher
+{
+    /// Adds `value`, returning whether it was newly added.  Nothing is sent when it was
+    /// already a member.
+    pub fn insert(&mut self, value: T) -> Result<bool> {
+        self.inner.insert_if_absent(value, ())
+    }
+
+    /// Removes `value`, returning whether it was present
+    pub fn remove<Q>(&mut self, value: &Q) -> Result<bool>
+        where T: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        Ok(self.inner.remove(value)?.is_some())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.inner.clear()
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+        where T: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.inner.contains_key(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+/// The reading half of a set, see `ReadOnlyHashMap`
+pub struct ReadOnlyHashSet<T, S = RandomState> {
+    inner: ReadOnlyHashMap<T, (), S>,
+}
+
+impl<T, S> ReadOnlyHashSet<T, S>
+    where T: Eq + Hash + Clone,
+          S: BuildHasher
+{
+    pub fn contains<Q>(&self, value: &Q) -> Result<bool>
+        where T: Borrow<Q>,
+              Q: ?Sized + Hash + Eq
+    {
+        self.inner.contains_key(value)
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.inner.is_empty()
+    }
+
+    /// Point-in-time copy of every member
+    pub fn iter(&self) -> Result<Vec<T>> {
+        self.inner.keys()
+    }
+
+    /// See `ReadOnlyHashMap::refresh`
+    pub fn refresh(&self) -> Result<()> {
+        self.inner.refresh()
+    }
+}